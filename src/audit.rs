@@ -0,0 +1,316 @@
+//! Checking of the crates in a vendor directory against the security
+//! advisories in a local clone of the RustSec advisory database, see
+//! https://github.com/RustSec/advisory-db.
+
+use crate::{source_paths, VendoredChecksums, VendoredManifest};
+use crate::{CHECKSUM_FILE_NAME, SOURCES_FILE_NAME, STUB_FILE_NAME};
+use cargo::util::{CargoResult, CargoResultExt};
+use semver::{Version, VersionReq};
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The source advisories in the database apply to.
+const CRATES_IO_SOURCE: &str = "registry+https://github.com/rust-lang/crates.io-index";
+
+/// An advisory which isn't reported, along with why that's fine.
+#[derive(Deserialize)]
+pub struct AuditIgnore {
+    /// The id of the advisory, like `RUSTSEC-2019-0001`.
+    pub id: String,
+    /// Why the advisory doesn't apply, which must be given.
+    #[serde(default)]
+    pub reason: String,
+}
+
+#[derive(Deserialize)]
+struct IgnoreFile {
+    #[serde(default)]
+    ignore: Vec<AuditIgnore>,
+}
+
+/// The result of auditing a vendor directory with [`audit`].
+pub struct AuditReport {
+    /// How many vendored crates were checked.
+    pub crates: usize,
+    /// The advisories affecting vendored crates, sorted by crate and
+    /// advisory id.
+    pub findings: Vec<AuditFinding>,
+    /// Ignored advisories which don't affect any vendored crate, and can be
+    /// removed from the ignore list.
+    pub unused_ignores: Vec<String>,
+}
+
+/// An advisory which affects the vendored version of a crate.
+pub struct AuditFinding {
+    /// The id of the advisory, like `RUSTSEC-2019-0001`.
+    pub id: String,
+    pub name: String,
+    pub version: Version,
+    /// Directory of the vendored crate.
+    pub path: PathBuf,
+    pub title: String,
+    pub url: Option<String>,
+    /// The kind of an informational advisory, like `unmaintained`, which
+    /// isn't about a vulnerability.
+    pub informational: Option<String>,
+    /// Requirements matching the versions with a fix.
+    pub patched: Vec<String>,
+    /// The justification from the ignore list, if the advisory is ignored.
+    pub ignored: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AdvisoryFile {
+    advisory: Advisory,
+    #[serde(default)]
+    versions: Versions,
+}
+
+#[derive(Deserialize)]
+struct Advisory {
+    id: String,
+    package: String,
+    title: Option<String>,
+    url: Option<String>,
+    informational: Option<String>,
+    withdrawn: Option<String>,
+    // The format used before `[versions]` was introduced
+    #[serde(default)]
+    patched_versions: Vec<String>,
+    #[serde(default)]
+    unaffected_versions: Vec<String>,
+}
+
+#[derive(Deserialize, Default)]
+struct Versions {
+    #[serde(default)]
+    patched: Vec<String>,
+    #[serde(default)]
+    unaffected: Vec<String>,
+}
+
+impl AuditReport {
+    /// Returns whether no vulnerability affects a vendored crate, apart from
+    /// ignored ones.
+    pub fn is_ok(&self) -> bool {
+        self.findings
+            .iter()
+            .all(|f| f.informational.is_some() || f.ignored.is_some())
+    }
+}
+
+/// Reads a list of advisories to ignore from the TOML file at `path`, which
+/// has an `[[ignore]]` table with an `id` and a `reason` for each of them.
+pub fn read_audit_ignores(path: &Path) -> CargoResult<Vec<AuditIgnore>> {
+    let contents =
+        fs::read_to_string(path).chain_err(|| format!("failed to read `{}`", path.display()))?;
+    let file: IgnoreFile =
+        toml::from_str(&contents).chain_err(|| format!("failed to parse `{}`", path.display()))?;
+    check_reasons(&file.ignore)
+        .chain_err(|| format!("invalid ignore list `{}`", path.display()))?;
+    Ok(file.ignore)
+}
+
+/// Fails if any of `ignores` doesn't give a reason.
+fn check_reasons(ignores: &[AuditIgnore]) -> CargoResult<()> {
+    for ignore in ignores {
+        if ignore.reason.trim().is_empty() {
+            failure::bail!(
+                "advisory `{}` is ignored without a reason, \
+                 every ignored advisory needs a justification",
+                ignore.id
+            )
+        }
+    }
+    Ok(())
+}
+
+/// Matches the name and version of every crate in the vendor directory at
+/// `path` against the advisories in the `advisory_db` checkout.
+///
+/// Only crates vendored from crates.io are checked, as that's what the
+/// advisories are about, and so are crates whose source isn't recorded.
+/// Stubs of packages which weren't needed are never built, so they aren't
+/// checked either. Withdrawn advisories are skipped. Every one of `ignores`
+/// must give a reason.
+pub fn audit(path: &Path, advisory_db: &Path, ignores: &[AuditIgnore]) -> CargoResult<AuditReport> {
+    check_reasons(ignores)?;
+    if !path.is_dir() {
+        failure::bail!("vendor directory `{}` does not exist", path.display())
+    }
+    let crates_dir = advisory_db.join("crates");
+    if !crates_dir.is_dir() {
+        failure::bail!(
+            "`{}` doesn't look like a checkout of the advisory database, \
+             it has no `crates` directory",
+            advisory_db.display()
+        )
+    }
+
+    let crates = vendored_crates(path)?;
+    let mut advisories = BTreeMap::new();
+    for name in crates.iter().map(|c| &c.1.package.name) {
+        if advisories.contains_key(name) {
+            continue;
+        }
+        let dir = crates_dir.join(name);
+        let list = if dir.is_dir() {
+            read_advisories(&dir)?
+        } else {
+            Vec::new()
+        };
+        advisories.insert(name.clone(), list);
+    }
+
+    let ignores = ignores
+        .iter()
+        .map(|i| (i.id.as_str(), i.reason.as_str()))
+        .collect::<BTreeMap<_, _>>();
+    let mut used = BTreeSet::new();
+    let mut findings = Vec::new();
+    for (dir, manifest) in crates.iter() {
+        let package = &manifest.package;
+        for advisory in advisories[&package.name].iter() {
+            if advisory.advisory.package != package.name
+                || !is_affected(advisory, &package.version)?
+            {
+                continue;
+            }
+            let ignored = ignores.get(advisory.advisory.id.as_str());
+            if ignored.is_some() {
+                used.insert(advisory.advisory.id.as_str());
+            }
+            let mut patched = advisory.versions.patched.clone();
+            patched.extend(advisory.advisory.patched_versions.iter().cloned());
+            findings.push(AuditFinding {
+                id: advisory.advisory.id.clone(),
+                name: package.name.clone(),
+                version: package.version.clone(),
+                path: dir.clone(),
+                title: advisory.advisory.title.clone().unwrap_or_default(),
+                url: advisory.advisory.url.clone(),
+                informational: advisory.advisory.informational.clone(),
+                patched,
+                ignored: ignored.map(|r| r.to_string()),
+            });
+        }
+    }
+    findings.sort_by(|a, b| (&a.name, &a.version, &a.id).cmp(&(&b.name, &b.version, &b.id)));
+    let unused_ignores = ignores
+        .keys()
+        .filter(|id| !used.contains(*id))
+        .map(|id| id.to_string())
+        .collect();
+    Ok(AuditReport {
+        crates: crates.len(),
+        findings,
+        unused_ignores,
+    })
+}
+
+/// Lists the crates in the vendor directory at `path` which are audited.
+fn vendored_crates(path: &Path) -> CargoResult<Vec<(PathBuf, VendoredManifest)>> {
+    let merge_sources = !path.join(SOURCES_FILE_NAME).exists();
+    let mut crates = Vec::new();
+    for source in source_paths(path, merge_sources)? {
+        if !source.is_dir() {
+            continue;
+        }
+        for entry in fs::read_dir(&source)? {
+            let dir = entry?.path();
+            let manifest = dir.join("Cargo.toml");
+            if !manifest.exists() || dir.join(STUB_FILE_NAME).exists() {
+                continue;
+            }
+            let cksum = dir.join(CHECKSUM_FILE_NAME);
+            if cksum.exists() {
+                let checksums: VendoredChecksums = serde_json::from_slice(&fs::read(&cksum)?)
+                    .chain_err(|| format!("failed to parse `{}`", cksum.display()))?;
+                match checksums.source {
+                    Some(ref source) if source != CRATES_IO_SOURCE => continue,
+                    _ => {}
+                }
+            }
+            let contents = fs::read_to_string(&manifest)?;
+            let manifest: VendoredManifest = toml::from_str(&contents)
+                .chain_err(|| format!("failed to parse `{}`", manifest.display()))?;
+            crates.push((dir, manifest));
+        }
+    }
+    crates.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(crates)
+}
+
+/// Reads the advisories in `dir`, which are either TOML files or, in the
+/// current format of the database, Markdown files starting with a fenced
+/// block of TOML.
+fn read_advisories(dir: &Path) -> CargoResult<Vec<AdvisoryFile>> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        match path.extension().and_then(|s| s.to_str()) {
+            Some("md") | Some("toml") => paths.push(path),
+            _ => {}
+        }
+    }
+    paths.sort();
+
+    let mut advisories = Vec::new();
+    for path in paths {
+        let contents = fs::read_to_string(&path)?;
+        let advisory = parse_advisory(&path, &contents)
+            .chain_err(|| format!("failed to parse advisory `{}`", path.display()))?;
+        if advisory.advisory.withdrawn.is_none() {
+            advisories.push(advisory);
+        }
+    }
+    Ok(advisories)
+}
+
+fn parse_advisory(path: &Path, contents: &str) -> CargoResult<AdvisoryFile> {
+    if path.extension().and_then(|s| s.to_str()) == Some("toml") {
+        return Ok(toml::from_str(contents)?);
+    }
+    let start = match contents.find("```toml") {
+        Some(start) => start + "```toml".len(),
+        None => failure::bail!("no block of TOML found"),
+    };
+    let end = match contents[start..].find("```") {
+        Some(end) => start + end,
+        None => failure::bail!("the block of TOML isn't closed"),
+    };
+    let mut advisory: AdvisoryFile = toml::from_str(&contents[start..end])?;
+    if advisory.advisory.title.is_none() {
+        // The title is the first heading of the Markdown
+        advisory.advisory.title = contents[end..]
+            .lines()
+            .find(|l| l.starts_with("# "))
+            .map(|l| l[2..].trim().to_string());
+    }
+    Ok(advisory)
+}
+
+/// Returns whether `version` is neither patched nor unaffected.
+fn is_affected(advisory: &AdvisoryFile, version: &Version) -> CargoResult<bool> {
+    let requirements = advisory
+        .versions
+        .patched
+        .iter()
+        .chain(advisory.versions.unaffected.iter())
+        .chain(advisory.advisory.patched_versions.iter())
+        .chain(advisory.advisory.unaffected_versions.iter());
+    for requirement in requirements {
+        let req = VersionReq::parse(requirement).chain_err(|| {
+            format!(
+                "invalid version requirement `{}` in advisory {}",
+                requirement, advisory.advisory.id
+            )
+        })?;
+        if req.matches(version) {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
@@ -0,0 +1,78 @@
+//! Merging of the generated source replacement configuration into an
+//! existing `.cargo/config` file.
+
+use crate::VendorConfig;
+use cargo::util::{CargoResult, CargoResultExt};
+use std::fs;
+use std::path::Path;
+use toml::value::{Table, Value};
+
+/// Name of the directory source used when all sources are merged.
+pub(crate) const MERGED_SOURCE_NAME: &str = "vendored-sources";
+
+/// Prefix of the directory sources used when sources are kept separate.
+pub(crate) const SEPARATE_SOURCE_PREFIX: &str = "vendor+";
+
+/// Writes `vendor_config` into the Cargo configuration file at `path`.
+///
+/// If the file already exists only the `[source.*]` tables generated by a
+/// previous run of `cargo vendor` are replaced, all other configuration is
+/// left untouched. Running this twice with the same `vendor_config` produces
+/// the same file. Note that comments in the existing file are not preserved.
+pub fn write_config(vendor_config: &VendorConfig, path: &Path) -> CargoResult<()> {
+    let mut table = if path.exists() {
+        let contents = fs::read_to_string(path)
+            .chain_err(|| format!("failed to read `{}`", path.display()))?;
+        toml::from_str::<Table>(&contents)
+            .chain_err(|| format!("failed to parse `{}`", path.display()))?
+    } else {
+        Table::new()
+    };
+
+    let new_sources = match Value::try_from(vendor_config)? {
+        Value::Table(mut t) => match t.remove("source") {
+            Some(Value::Table(sources)) => sources,
+            _ => Table::new(),
+        },
+        _ => unreachable!(),
+    };
+
+    let mut sources = match table.remove("source") {
+        Some(Value::Table(sources)) => sources,
+        Some(_) => failure::bail!("`source` in `{}` is not a table", path.display()),
+        None => Table::new(),
+    };
+    let owned = sources
+        .iter()
+        .filter(|(name, source)| new_sources.contains_key(*name) || is_vendor_source(name, source))
+        .map(|(name, _)| name.clone())
+        .collect::<Vec<_>>();
+    for name in owned {
+        sources.remove(&name);
+    }
+    sources.extend(new_sources);
+    table.insert("source".to_string(), Value::Table(sources));
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .chain_err(|| format!("failed to create: `{}`", parent.display()))?;
+    }
+    fs::write(path, toml::to_string(&Value::Table(table))?)
+        .chain_err(|| format!("failed to write `{}`", path.display()))?;
+    Ok(())
+}
+
+/// Returns whether the `[source.<name>]` table was generated by us, either
+/// as one of the vendored directory sources or as a replacement pointing at
+/// one of them.
+fn is_vendor_source(name: &str, source: &Value) -> bool {
+    let is_ours =
+        |name: &str| name == MERGED_SOURCE_NAME || name.starts_with(SEPARATE_SOURCE_PREFIX);
+    if is_ours(name) {
+        return true;
+    }
+    match source.get("replace-with").and_then(|v| v.as_str()) {
+        Some(replace_with) => is_ours(replace_with),
+        None => false,
+    }
+}
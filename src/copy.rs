@@ -0,0 +1,220 @@
+//! Copying of crate sources into the vendor directory.
+//!
+//! Every file is hashed while it's copied, so its contents are only read
+//! once, and the files of all crates are copied by a pool of threads. Files
+//! may also be linked rather than copied, see [`LinkMode`].
+
+use crate::{hex, sha256};
+use cargo::core::PackageId;
+use cargo::util::{CargoResult, CargoResultExt, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Once;
+
+/// How the files of crates are placed into the vendor directory.
+///
+/// Every mode other than `Copy` falls back to copying a file which can't be
+/// linked, e.g. because the vendor directory is on a different file system
+/// than Cargo's cache.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LinkMode {
+    /// Copy every file.
+    Copy,
+    /// Hard link files to the sources Cargo extracted. Hard linked files are
+    /// made read-only, as editing them would also change Cargo's copy, which
+    /// makes the files in Cargo's cache read-only as well. That's only done
+    /// on Unix, as elsewhere read-only files can't be deleted, which would
+    /// keep Cargo's cache from being cleaned up, so files are copied instead.
+    Hardlink,
+    /// Clone files with a copy-on-write reflink where the file system
+    /// supports it, which is currently only done on Linux.
+    Reflink,
+    /// Reflink files if possible, and otherwise hard link them on Unix.
+    Auto,
+}
+
+/// The files of one crate to copy from `src` to `dst`, which is cleared
+/// first.
+pub(crate) struct CopyJob<'a> {
+    pub(crate) id: PackageId,
+    pub(crate) src: &'a Path,
+    pub(crate) paths: Vec<PathBuf>,
+    pub(crate) dst: PathBuf,
+    pub(crate) link_mode: LinkMode,
+}
+
+/// Returns whether the file at `relative` in a crate's sources is vendored.
+pub(crate) fn is_vendored(relative: &Path) -> bool {
+    match relative.to_str() {
+        // Skip git config files as they're not relevant to builds most of
+        // the time and if we respect them (e.g.  in git) then it'll
+        // probably mess with the checksums when a vendor dir is checked
+        // into someone else's source control
+        Some(".gitattributes") | Some(".gitignore") | Some(".git") => false,
+
+        // Temporary Cargo files
+        Some(".cargo-ok") => false,
+
+        // Skip patch-style orig/rej files. Published crates on crates.io
+        // have `Cargo.toml.orig` which we don't want to use here and
+        // otherwise these are rarely used as part of the build process.
+        Some(filename) => !filename.ends_with(".orig") && !filename.ends_with(".rej"),
+        _ => true,
+    }
+}
+
+/// Copies the files of all `jobs`, returning the checksums of the files of
+/// each job in the same order as `jobs`.
+///
+/// The files are spread over one thread per CPU. The result doesn't depend
+/// on the order they're copied in, and if several files fail to copy the
+/// error for the first of them in `jobs` is returned.
+///
+/// The directory of a job is only cleared just before its first file is
+/// copied, so a failure leaves the crates which weren't reached yet as they
+/// were.
+pub(crate) fn cp_sources(jobs: &[CopyJob]) -> CargoResult<Vec<BTreeMap<String, String>>> {
+    let files = jobs
+        .iter()
+        .enumerate()
+        .flat_map(|(i, job)| job.paths.iter().map(move |p| (i, p)))
+        .collect::<Vec<_>>();
+    let cleared = jobs.iter().map(|_| Once::new()).collect::<Vec<_>>();
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let threads = num_cpus::get().min(files.len()).max(1);
+
+    let work = || {
+        let mut results = Vec::new();
+        while !failed.load(Ordering::SeqCst) {
+            let n = next.fetch_add(1, Ordering::SeqCst);
+            let (job, path) = match files.get(n) {
+                Some(&(i, path)) => {
+                    cleared[i].call_once(|| {
+                        let _ = fs::remove_dir_all(&jobs[i].dst);
+                    });
+                    (&jobs[i], path)
+                }
+                None => break,
+            };
+            let result = copy_into(job.src, path, &job.dst, job.link_mode);
+            if result.is_err() {
+                failed.store(true, Ordering::SeqCst);
+            }
+            results.push((n, result));
+        }
+        results
+    };
+    let mut results = crossbeam_utils::thread::scope(|scope| {
+        let handles = (0..threads)
+            .map(|_| scope.spawn(|_| work()))
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().expect("copying thread panicked"))
+            .collect::<Vec<_>>()
+    })
+    .expect("copying thread panicked");
+    results.sort_by_key(|&(n, _)| n);
+
+    let mut cksums = jobs.iter().map(|_| BTreeMap::new()).collect::<Vec<_>>();
+    for (n, result) in results {
+        let job = files[n].0;
+        let (relative, cksum) = result
+            .chain_err(|| format!("failed to copy over vendored sources for: {}", jobs[job].id))?;
+        cksums[job].insert(relative, cksum);
+    }
+    Ok(cksums)
+}
+
+/// Copies or links the file at `path` in `src` to the same place in `dst`,
+/// returning its path relative to `src` and its checksum.
+fn copy_into(
+    src: &Path,
+    path: &Path,
+    dst: &Path,
+    link_mode: LinkMode,
+) -> CargoResult<(String, String)> {
+    let relative = path.strip_prefix(src).unwrap();
+
+    // Join pathname components individually to make sure that the joined
+    // path uses the correct directory separators everywhere, since
+    // `relative` may use Unix-style and `dst` may require Windows-style
+    // backslashes.
+    let dst = relative
+        .iter()
+        .fold(dst.to_owned(), |acc, component| acc.join(&component));
+
+    fs::create_dir_all(dst.parent().unwrap())?;
+    let cksum = link_or_copy(path, &dst, link_mode)
+        .chain_err(|| format!("failed to copy `{}` to `{}`", path.display(), dst.display()))?;
+    Ok((relative.to_str().unwrap().replace("\\", "/"), cksum))
+}
+
+/// Places `src` at `dst` as `link_mode` asks for, returning the checksum of
+/// its contents.
+pub(crate) fn link_or_copy(src: &Path, dst: &Path, link_mode: LinkMode) -> CargoResult<String> {
+    let reflink = link_mode == LinkMode::Reflink || link_mode == LinkMode::Auto;
+    let hardlink = cfg!(unix) && (link_mode == LinkMode::Hardlink || link_mode == LinkMode::Auto);
+    if (reflink && reflink_file(src, dst).is_ok()) || (hardlink && hard_link(src, dst).is_ok()) {
+        return Ok(sha256(dst)?);
+    }
+    copy_file(src, dst)
+}
+
+/// Hard links `dst` to `src` and makes it read-only, which also applies to
+/// `src` as they're the same file.
+fn hard_link(src: &Path, dst: &Path) -> io::Result<()> {
+    fs::hard_link(src, dst)?;
+    let mut permissions = fs::metadata(dst)?.permissions();
+    permissions.set_readonly(true);
+    fs::set_permissions(dst, permissions)
+}
+
+/// Clones `src` to `dst` with the `FICLONE` ioctl, which shares the data of
+/// both files until either is modified.
+#[cfg(target_os = "linux")]
+fn reflink_file(src: &Path, dst: &Path) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    const FICLONE: libc::c_ulong = 0x4004_9409;
+    let input = File::open(src)?;
+    let output = File::create(dst)?;
+    if unsafe { libc::ioctl(output.as_raw_fd(), FICLONE as _, input.as_raw_fd()) } == -1 {
+        let err = io::Error::last_os_error();
+        drop(output);
+        fs::remove_file(dst)?;
+        return Err(err);
+    }
+    output.set_permissions(input.metadata()?.permissions())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reflink_file(_src: &Path, _dst: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "reflinks aren't supported on this platform",
+    ))
+}
+
+/// Copies `src` to `dst` along with its permissions, returning the checksum
+/// of its contents.
+fn copy_file(src: &Path, dst: &Path) -> CargoResult<String> {
+    let mut input = File::open(src)?;
+    let mut output = File::create(dst)?;
+    let mut sha = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = input.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        sha.update(&buf[..n]);
+        output.write_all(&buf[..n])?;
+    }
+    output.set_permissions(input.metadata()?.permissions())?;
+    Ok(hex(&sha.finish()))
+}
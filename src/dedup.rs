@@ -0,0 +1,123 @@
+//! Deduplication of identical files across vendored crates, which are common
+//! when several versions of a crate are vendored.
+
+use crate::{relative_path, Vendored, VendoredChecksums, CHECKSUM_FILE_NAME};
+use cargo::util::{CargoResult, CargoResultExt};
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// How duplicate files are replaced by [`VendorOptions::dedup`].
+///
+/// [`VendorOptions::dedup`]: crate::VendorOptions::dedup
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DedupMode {
+    /// Hard link duplicates to the first copy of the file.
+    Hardlink,
+    /// Replace duplicates with relative symbolic links to the first copy of
+    /// the file, which keeps the savings when the vendor directory is
+    /// checked in or archived.
+    Symlink,
+}
+
+/// The files deduplicated by [`VendorOptions::dedup`].
+///
+/// [`VendorOptions::dedup`]: crate::VendorOptions::dedup
+#[derive(Debug, Default)]
+pub struct DedupReport {
+    /// How many files are links to an identical file in another place.
+    pub files: usize,
+    /// The combined size of those files.
+    pub bytes: u64,
+}
+
+/// Replaces files of the crates in `vendored` which are identical to a file
+/// seen before, according to the checksums in their `.cargo-checksum.json`,
+/// with links to it.
+///
+/// Crates are visited in the order they're listed in, so the same file is
+/// kept on every run. Files which are already linked are counted but left
+/// alone.
+pub(crate) fn dedup(vendored: &Vendored, mode: DedupMode) -> CargoResult<DedupReport> {
+    let mut first = BTreeMap::<String, PathBuf>::new();
+    let mut report = DedupReport::default();
+    for pkg in vendored.packages.iter() {
+        let cksum = pkg.path.join(CHECKSUM_FILE_NAME);
+        if !cksum.is_file() {
+            continue;
+        }
+        let checksums: VendoredChecksums = serde_json::from_slice(&fs::read(&cksum)?)
+            .chain_err(|| format!("failed to parse `{}`", cksum.display()))?;
+        for (file, sha) in checksums.files {
+            let path = file
+                .split('/')
+                .fold(pkg.path.clone(), |acc, component| acc.join(component));
+            let original = match first.entry(sha) {
+                Entry::Occupied(e) => e.into_mut(),
+                Entry::Vacant(e) => {
+                    e.insert(path);
+                    continue;
+                }
+            };
+            let size = fs::metadata(&path)?.len();
+            if size == 0 {
+                continue;
+            }
+            link(original, &path, mode)
+                .chain_err(|| format!("failed to deduplicate `{}`", path.display()))?;
+            report.files += 1;
+            report.bytes += size;
+        }
+    }
+    Ok(report)
+}
+
+/// Replaces `path` with a link to `original`, unless it already is one.
+fn link(original: &Path, path: &Path, mode: DedupMode) -> io::Result<()> {
+    let tmp = path.with_file_name(format!(
+        ".{}.dedup",
+        path.file_name().unwrap().to_string_lossy()
+    ));
+    let _ = fs::remove_file(&tmp);
+    match mode {
+        DedupMode::Hardlink => {
+            if same_file(original, path)? {
+                return Ok(());
+            }
+            fs::hard_link(original, &tmp)?;
+        }
+        DedupMode::Symlink => {
+            let target = relative_path(path.parent().unwrap(), original);
+            if fs::read_link(path).ok().as_ref() == Some(&target) {
+                return Ok(());
+            }
+            symlink(&target, &tmp)?;
+        }
+    }
+    fs::rename(&tmp, path)
+}
+
+#[cfg(unix)]
+fn same_file(a: &Path, b: &Path) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+
+    let (a, b) = (fs::symlink_metadata(a)?, fs::symlink_metadata(b)?);
+    Ok(a.dev() == b.dev() && a.ino() == b.ino())
+}
+
+#[cfg(not(unix))]
+fn same_file(_a: &Path, _b: &Path) -> io::Result<bool> {
+    Ok(false)
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, path: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, path)
+}
+
+#[cfg(windows)]
+fn symlink(target: &Path, path: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(target, path)
+}
@@ -0,0 +1,333 @@
+//! Comparison of the contents of a vendor directory before and after it's
+//! synced, to make reviewing updates of vendored crates easier.
+
+use crate::info::VendorInfo;
+use crate::{source_paths, Vendored, VendoredChecksums, VendoredManifest};
+use crate::{CHECKSUM_FILE_NAME, SOURCES_FILE_NAME, STUB_FILE_NAME};
+use cargo::util::CargoResult;
+use semver::Version;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The changes made to a vendor directory by [`vendor`](crate::vendor).
+#[derive(Serialize, Default)]
+pub struct VendorDiff {
+    /// One entry per crate which changed, sorted by name and version.
+    pub crates: Vec<CrateChange>,
+}
+
+/// How a vendored crate changed.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Upgraded,
+    Downgraded,
+    /// The same version is vendored, but its files changed, e.g. because it
+    /// was patched or is now vendored from a different source.
+    Modified,
+}
+
+/// A crate which was added to, removed from or changed in the vendor
+/// directory.
+#[derive(Serialize)]
+pub struct CrateChange {
+    pub name: String,
+    pub kind: ChangeKind,
+    pub old_version: Option<String>,
+    pub new_version: Option<String>,
+    /// The source the crate was previously vendored from, if known.
+    pub old_source: Option<String>,
+    pub new_source: Option<String>,
+    /// Changes to the crate's files according to its `.cargo-checksum.json`.
+    pub files: FileChanges,
+}
+
+/// Files, relative to a crate's root, which changed between two versions of
+/// a vendored crate.
+#[derive(Serialize, Default)]
+pub struct FileChanges {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub modified: Vec<String>,
+}
+
+/// The crates found in a vendor directory.
+pub(crate) struct Snapshot {
+    crates: Vec<SnapshotCrate>,
+}
+
+struct SnapshotCrate {
+    name: String,
+    version: Version,
+    path: PathBuf,
+    source: Option<String>,
+    package: Option<String>,
+    files: BTreeMap<String, String>,
+}
+
+impl Snapshot {
+    /// Reads the crates currently vendored in `path`, which may not exist.
+    ///
+    /// Stubs of packages which weren't needed don't count as vendored, and
+    /// neither do directories which can't be identified as a crate.
+    pub(crate) fn read(path: &Path) -> CargoResult<Snapshot> {
+        let mut crates = Vec::new();
+        if !path.is_dir() {
+            return Ok(Snapshot { crates });
+        }
+        let merge_sources = !path.join(SOURCES_FILE_NAME).exists();
+        for source in source_paths(path, merge_sources)? {
+            if !source.is_dir() {
+                continue;
+            }
+            for entry in fs::read_dir(&source)? {
+                let dir = entry?.path();
+                if !dir.join("Cargo.toml").exists() || dir.join(STUB_FILE_NAME).exists() {
+                    continue;
+                }
+                if let Some(krate) = read_crate(&dir) {
+                    crates.push(krate);
+                }
+            }
+        }
+        Ok(Snapshot { crates })
+    }
+
+    /// Fills in the sources of the crates from what was just vendored.
+    pub(crate) fn set_sources(&mut self, vendored: &Vendored) {
+        let sources = vendored
+            .packages
+            .iter()
+            .map(|p| (p.path.clone(), p.id.source_id().to_url().to_string()))
+            .collect::<HashMap<_, _>>();
+        for krate in self.crates.iter_mut() {
+            let path = krate
+                .path
+                .canonicalize()
+                .unwrap_or_else(|_| krate.path.clone());
+            if let Some(source) = sources.get(&path) {
+                krate.source = Some(source.clone());
+            }
+        }
+    }
+}
+
+/// Reads the crate vendored in `dir`, named after its manifest or else its
+/// `.cargo-vendor-info.json`, if either can be read.
+fn read_crate(dir: &Path) -> Option<SnapshotCrate> {
+    let info = VendorInfo::read(dir).ok().and_then(|info| info);
+    let manifest = fs::read_to_string(dir.join("Cargo.toml"))
+        .ok()
+        .and_then(|contents| toml::from_str::<VendoredManifest>(&contents).ok());
+    let (name, version) = match (manifest, &info) {
+        (Some(manifest), _) => (manifest.package.name, manifest.package.version),
+        (None, Some(info)) => (info.name.clone(), Version::parse(&info.version).ok()?),
+        (None, None) => return None,
+    };
+    let checksums = fs::read(dir.join(CHECKSUM_FILE_NAME))
+        .ok()
+        .and_then(|contents| serde_json::from_slice::<VendoredChecksums>(&contents).ok());
+    let (package, files) = match checksums {
+        Some(checksums) => (checksums.package, checksums.files),
+        None => (None, BTreeMap::new()),
+    };
+    Some(SnapshotCrate {
+        name,
+        version,
+        path: dir.to_path_buf(),
+        source: info.map(|info| info.source),
+        package,
+        files,
+    })
+}
+
+/// Computes the changes from the `old` to the `new` contents of a vendor
+/// directory.
+///
+/// Crates are matched up by name. If a crate has a single version in both
+/// which differ it's reported as upgraded or downgraded, otherwise versions
+/// only in `old` are removed and versions only in `new` are added.
+pub(crate) fn diff(old: &Snapshot, new: &Snapshot) -> VendorDiff {
+    let mut by_name = BTreeMap::new();
+    for krate in old.crates.iter() {
+        by_name
+            .entry(krate.name.as_str())
+            .or_insert_with(|| (Vec::new(), Vec::new()))
+            .0
+            .push(krate);
+    }
+    for krate in new.crates.iter() {
+        by_name
+            .entry(krate.name.as_str())
+            .or_insert_with(|| (Vec::new(), Vec::new()))
+            .1
+            .push(krate);
+    }
+
+    let mut crates = Vec::new();
+    for (_, (old, new)) in by_name {
+        let old_only = old
+            .iter()
+            .filter(|o| !new.iter().any(|n| n.version == o.version))
+            .collect::<Vec<_>>();
+        let new_only = new
+            .iter()
+            .filter(|n| !old.iter().any(|o| n.version == o.version))
+            .collect::<Vec<_>>();
+
+        for n in new.iter() {
+            if let Some(o) = old.iter().find(|o| o.version == n.version) {
+                let change = change(ChangeKind::Modified, Some(o), Some(n));
+                if o.package != n.package || !change.files.is_empty() {
+                    crates.push(change);
+                }
+            }
+        }
+        if let ([o], [n]) = (&old_only[..], &new_only[..]) {
+            let kind = if n.version > o.version {
+                ChangeKind::Upgraded
+            } else {
+                ChangeKind::Downgraded
+            };
+            crates.push(change(kind, Some(o), Some(n)));
+            continue;
+        }
+        for o in old_only {
+            crates.push(change(ChangeKind::Removed, Some(o), None));
+        }
+        for n in new_only {
+            crates.push(change(ChangeKind::Added, None, Some(n)));
+        }
+    }
+    crates.sort_by(|a, b| {
+        let version = |c: &CrateChange| {
+            c.new_version
+                .as_ref()
+                .or_else(|| c.old_version.as_ref())
+                .and_then(|v| Version::parse(v).ok())
+        };
+        (&a.name, version(a)).cmp(&(&b.name, version(b)))
+    });
+    VendorDiff { crates }
+}
+
+fn change(
+    kind: ChangeKind,
+    old: Option<&SnapshotCrate>,
+    new: Option<&SnapshotCrate>,
+) -> CrateChange {
+    let empty = BTreeMap::new();
+    let old_files = old.map(|c| &c.files).unwrap_or(&empty);
+    let new_files = new.map(|c| &c.files).unwrap_or(&empty);
+    let names = old_files
+        .keys()
+        .chain(new_files.keys())
+        .collect::<BTreeSet<_>>();
+    let mut files = FileChanges::default();
+    for name in names {
+        match (old_files.get(name), new_files.get(name)) {
+            (Some(_), None) => files.removed.push(name.clone()),
+            (None, Some(_)) => files.added.push(name.clone()),
+            (Some(a), Some(b)) if a != b => files.modified.push(name.clone()),
+            _ => {}
+        }
+    }
+
+    // The old source is recorded in `.cargo-vendor-info.json`, and otherwise
+    // a crate vendored from the same package keeps its source
+    let old_source = old.and_then(|o| match new {
+        Some(n) if o.source.is_none() && o.package.is_some() && o.package == n.package => {
+            n.source.clone()
+        }
+        _ => o.source.clone(),
+    });
+    CrateChange {
+        name: old.or(new).unwrap().name.clone(),
+        kind,
+        old_version: old.map(|c| c.version.to_string()),
+        new_version: new.map(|c| c.version.to_string()),
+        old_source,
+        new_source: new.and_then(|c| c.source.clone()),
+        files,
+    }
+}
+
+impl VendorDiff {
+    /// Returns whether nothing changed.
+    pub fn is_empty(&self) -> bool {
+        self.crates.is_empty()
+    }
+}
+
+impl FileChanges {
+    /// Returns whether no files changed.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+impl fmt::Display for ChangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            ChangeKind::Added => "added",
+            ChangeKind::Removed => "removed",
+            ChangeKind::Upgraded => "upgraded",
+            ChangeKind::Downgraded => "downgraded",
+            ChangeKind::Modified => "modified",
+        };
+        f.pad(s)
+    }
+}
+
+/// Formats the changes as a table with one row per crate.
+impl fmt::Display for VendorDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let rows = self
+            .crates
+            .iter()
+            .map(|c| {
+                let files = &c.files;
+                [
+                    c.kind.to_string(),
+                    c.name.clone(),
+                    c.old_version.clone().unwrap_or_default(),
+                    c.new_version.clone().unwrap_or_default(),
+                    format!(
+                        "+{} -{} ~{}",
+                        files.added.len(),
+                        files.removed.len(),
+                        files.modified.len()
+                    ),
+                ]
+            })
+            .collect::<Vec<_>>();
+        let header = [
+            "CHANGE".to_string(),
+            "CRATE".to_string(),
+            "OLD".to_string(),
+            "NEW".to_string(),
+            "FILES".to_string(),
+        ];
+        let mut widths = [0; 5];
+        for row in rows.iter().chain(Some(&header)) {
+            for (width, cell) in widths.iter_mut().zip(row.iter()) {
+                *width = (*width).max(cell.len());
+            }
+        }
+        for row in Some(&header).into_iter().chain(rows.iter()) {
+            let line = row
+                .iter()
+                .zip(widths.iter())
+                .map(|(cell, width)| format!("{:1$}", cell, width))
+                .collect::<Vec<_>>()
+                .join("  ");
+            writeln!(f, "{}", line.trim_end())?;
+        }
+        Ok(())
+    }
+}
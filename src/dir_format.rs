@@ -0,0 +1,227 @@
+//! Templates naming the directories crates are vendored into, like
+//! `{name}-{version}` or `{source}/{name}-{version}`.
+//!
+//! Cargo only finds crates directly in the directory of a source, so the
+//! last component of a template names the directory of each crate, while
+//! any components before it name the directory of each source and can only
+//! use the placeholders which are the same for all crates of a source. So
+//! `{source}/{name}/{version}` can't be used, but
+//! `{source}-{hash}/{name}-{version}` can.
+
+use crate::{source_hash, source_kind};
+use cargo::core::{PackageId, SourceId};
+use cargo::util::CargoResult;
+use std::collections::HashMap;
+
+/// The placeholders which can be used in a template.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Placeholder {
+    /// The name of the crate.
+    Name,
+    /// The version of the crate.
+    Version,
+    /// The kind of source, `registry`, `git` or `path`.
+    Source,
+    /// The abbreviated commit of a git source, empty for other sources.
+    Rev,
+    /// The name of a registry, empty for other sources.
+    Registry,
+    /// A hash of the source, which tells apart sources of the same kind.
+    Hash,
+}
+
+const PLACEHOLDERS: &[(&str, Placeholder)] = &[
+    ("name", Placeholder::Name),
+    ("version", Placeholder::Version),
+    ("source", Placeholder::Source),
+    ("rev", Placeholder::Rev),
+    ("registry", Placeholder::Registry),
+    ("hash", Placeholder::Hash),
+];
+
+/// Length of an abbreviated commit, as in Cargo's output.
+const SHORT_REV_LEN: usize = 8;
+
+enum Piece {
+    Literal(String),
+    Placeholder(Placeholder),
+}
+
+/// A parsed `--dir-format` template.
+pub(crate) struct DirFormat {
+    template: String,
+    /// The components naming the directory of each source.
+    source_dir: Vec<Vec<Piece>>,
+    /// The component naming the directory of each crate.
+    crate_dir: Vec<Piece>,
+}
+
+impl DirFormat {
+    pub(crate) fn parse(template: &str) -> CargoResult<DirFormat> {
+        let mut components = template
+            .split('/')
+            .map(|component| parse_component(template, component))
+            .collect::<CargoResult<Vec<_>>>()?;
+        let crate_dir = components.pop().unwrap();
+        for component in components.iter() {
+            let per_crate = component.iter().find_map(|piece| match piece {
+                Piece::Placeholder(p) if !is_per_source(*p) => Some(*p),
+                _ => None,
+            });
+            if let Some(placeholder) = per_crate {
+                failure::bail!(
+                    "invalid directory format `{}`: `{{{}}}` can only be used in the \
+                     last component, as Cargo only finds crates directly in the \
+                     directory of their source",
+                    template,
+                    name(placeholder)
+                );
+            }
+        }
+        Ok(DirFormat {
+            template: template.to_string(),
+            source_dir: components,
+            crate_dir,
+        })
+    }
+
+    /// Returns whether the template names the directories of sources, which
+    /// needs sources not to be merged.
+    pub(crate) fn has_source_dir(&self) -> bool {
+        !self.source_dir.is_empty()
+    }
+
+    /// The directory of the source `id`, relative to the vendor directory,
+    /// if the template names it.
+    pub(crate) fn source_dir(
+        &self,
+        id: SourceId,
+        registry_names: &HashMap<SourceId, String>,
+    ) -> CargoResult<Option<String>> {
+        if self.source_dir.is_empty() {
+            return Ok(None);
+        }
+        let mut dirs = Vec::new();
+        for component in self.source_dir.iter() {
+            let dir = self.expand(component, &|p| expand_source(p, id, registry_names));
+            self.check_dir(&dir, &id.to_string())?;
+            dirs.push(dir);
+        }
+        Ok(Some(dirs.join("/")))
+    }
+
+    /// The name of the directory of the crate `id` in the directory of its
+    /// source.
+    pub(crate) fn crate_dir(
+        &self,
+        id: PackageId,
+        registry_names: &HashMap<SourceId, String>,
+    ) -> CargoResult<String> {
+        let dir = self.expand(&self.crate_dir, &|p| match p {
+            Placeholder::Name => id.name().to_string(),
+            Placeholder::Version => id.version().to_string(),
+            _ => expand_source(p, id.source_id(), registry_names),
+        });
+        self.check_dir(&dir, &id.to_string())?;
+        Ok(dir)
+    }
+
+    fn expand(&self, pieces: &[Piece], value: &dyn Fn(Placeholder) -> String) -> String {
+        pieces
+            .iter()
+            .map(|piece| match piece {
+                Piece::Literal(s) => s.clone(),
+                Piece::Placeholder(p) => value(*p),
+            })
+            .collect()
+    }
+
+    fn check_dir(&self, dir: &str, what: &str) -> CargoResult<()> {
+        if dir.is_empty() || dir.starts_with('.') || dir.contains('/') || dir.contains('\\') {
+            failure::bail!(
+                "the directory format `{}` names an invalid directory `{}` for {}",
+                self.template,
+                dir,
+                what
+            );
+        }
+        Ok(())
+    }
+}
+
+fn parse_component(template: &str, component: &str) -> CargoResult<Vec<Piece>> {
+    if component.is_empty() || component == "." || component == ".." {
+        failure::bail!(
+            "invalid directory format `{}`: `{}` isn't a directory name",
+            template,
+            component
+        );
+    }
+    let mut pieces = Vec::new();
+    let mut rest = component;
+    while let Some(start) = rest.find(|c| c == '{' || c == '}') {
+        if rest[start..].starts_with('}') {
+            failure::bail!("invalid directory format `{}`: unmatched `}}`", template);
+        }
+        if start > 0 {
+            pieces.push(Piece::Literal(rest[..start].to_string()));
+        }
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => failure::bail!("invalid directory format `{}`: unclosed `{{`", template),
+        };
+        let placeholder = &rest[start + 1..end];
+        match PLACEHOLDERS.iter().find(|(name, _)| *name == placeholder) {
+            Some((_, p)) => pieces.push(Piece::Placeholder(*p)),
+            None => failure::bail!(
+                "invalid directory format `{}`: unknown placeholder `{{{}}}`, expected \
+                 one of `{{name}}`, `{{version}}`, `{{source}}`, `{{rev}}`, `{{registry}}` \
+                 or `{{hash}}`",
+                template,
+                placeholder
+            ),
+        }
+        rest = &rest[end + 1..];
+    }
+    if !rest.is_empty() {
+        pieces.push(Piece::Literal(rest.to_string()));
+    }
+    Ok(pieces)
+}
+
+fn is_per_source(placeholder: Placeholder) -> bool {
+    placeholder == Placeholder::Source
+        || placeholder == Placeholder::Registry
+        || placeholder == Placeholder::Hash
+}
+
+fn name(placeholder: Placeholder) -> &'static str {
+    PLACEHOLDERS
+        .iter()
+        .find(|(_, p)| *p == placeholder)
+        .unwrap()
+        .0
+}
+
+fn expand_source(
+    placeholder: Placeholder,
+    id: SourceId,
+    registry_names: &HashMap<SourceId, String>,
+) -> String {
+    match placeholder {
+        Placeholder::Source => source_kind(id).to_string(),
+        Placeholder::Rev => id
+            .precise()
+            .filter(|_| id.is_git())
+            .map(|rev| rev.chars().take(SHORT_REV_LEN).collect())
+            .unwrap_or_default(),
+        Placeholder::Registry if id.is_default_registry() => "crates-io".to_string(),
+        Placeholder::Registry if id.is_registry() => match registry_names.get(&id) {
+            Some(name) => name.clone(),
+            None => id.url().host_str().unwrap_or_default().to_string(),
+        },
+        Placeholder::Registry => String::new(),
+        Placeholder::Hash => source_hash(id),
+        Placeholder::Name | Placeholder::Version => unreachable!(),
+    }
+}
@@ -0,0 +1,130 @@
+//! Reporting of what happens while vendoring, either as the usual status
+//! lines or as JSON messages for other tools to consume.
+
+use cargo::core::PackageId;
+use cargo::util::{CargoResult, Config};
+use serde_json::json;
+use std::path::Path;
+
+/// How progress is reported while vendoring.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessageFormat {
+    /// Human readable status lines on stderr.
+    Human,
+    /// One JSON object per line on stdout, each with a `reason` field naming
+    /// the kind of event. Status lines which have no corresponding event are
+    /// still printed on stderr.
+    Json,
+}
+
+/// Something which happened while vendoring.
+pub(crate) enum Event<'a> {
+    /// The workspace with the manifest at `manifest_path` was resolved.
+    Resolve {
+        manifest_path: &'a Path,
+        packages: usize,
+    },
+    /// The sources of a package are available at `path`.
+    Fetch { id: PackageId, path: &'a Path },
+    /// A package was already vendored in `path` and left as it is.
+    Fresh { id: PackageId, path: &'a Path },
+    /// A package was copied from `src` into the vendor directory.
+    Copy {
+        id: PackageId,
+        src: &'a Path,
+        dst: &'a Path,
+        files: usize,
+    },
+    /// A package isn't needed and was left out.
+    Skip { id: PackageId, reason: &'a str },
+    /// A crate which is no longer used was deleted from the vendor directory.
+    Delete { path: &'a Path },
+    /// Identical files were replaced with links, saving `bytes`.
+    Dedup { files: usize, bytes: u64 },
+}
+
+/// Reports `event` in the given `format`.
+pub(crate) fn emit(format: MessageFormat, config: &Config, event: Event) -> CargoResult<()> {
+    match format {
+        MessageFormat::Human => {
+            if let Some((status, message)) = event.status() {
+                config.shell().status(status, message)?;
+            }
+        }
+        MessageFormat::Json => println!("{}", event.to_json()),
+    }
+    Ok(())
+}
+
+impl<'a> Event<'a> {
+    fn status(&self) -> Option<(&'static str, String)> {
+        match *self {
+            Event::Copy { id, src, dst, .. } => Some((
+                "Vendoring",
+                format!("{} ({}) to {}", id, src.display(), dst.display()),
+            )),
+            Event::Skip { id, reason } => Some(("Skipping", format!("{} ({})", id, reason))),
+            Event::Dedup { files, bytes } => Some((
+                "Deduplicated",
+                format!("{} identical files, saving {} bytes", files, bytes),
+            )),
+            Event::Resolve { .. }
+            | Event::Fetch { .. }
+            | Event::Fresh { .. }
+            | Event::Delete { .. } => None,
+        }
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        match *self {
+            Event::Resolve {
+                manifest_path,
+                packages,
+            } => json!({
+                "reason": "resolve",
+                "manifest_path": manifest_path,
+                "packages": packages,
+            }),
+            Event::Fetch { id, path } => json!({
+                "reason": "fetch",
+                "package_id": id.to_string(),
+                "source": id.source_id().to_url().to_string(),
+                "path": path,
+            }),
+            Event::Fresh { id, path } => json!({
+                "reason": "fresh",
+                "package_id": id.to_string(),
+                "source": id.source_id().to_url().to_string(),
+                "path": path,
+            }),
+            Event::Copy {
+                id,
+                src,
+                dst,
+                files,
+            } => json!({
+                "reason": "copy",
+                "package_id": id.to_string(),
+                "source": id.source_id().to_url().to_string(),
+                "src_path": src,
+                "dst_path": dst,
+                "files": files,
+            }),
+            Event::Skip { id, reason } => json!({
+                "reason": "skip",
+                "package_id": id.to_string(),
+                "source": id.source_id().to_url().to_string(),
+                "why": reason,
+            }),
+            Event::Delete { path } => json!({
+                "reason": "delete",
+                "path": path,
+            }),
+            Event::Dedup { files, bytes } => json!({
+                "reason": "dedup",
+                "files": files,
+                "bytes": bytes,
+            }),
+        }
+    }
+}
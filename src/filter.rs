@@ -0,0 +1,173 @@
+//! Pruning of packages from the resolve graph which aren't actually needed
+//! to build a workspace, e.g. because they're only used on other platforms,
+//! only as dev-dependencies or are optional dependencies which are never
+//! activated.
+
+use cargo::core::dependency::Kind;
+use cargo::core::resolver::Method;
+use cargo::core::{Dependency, PackageId, PackageIdSpec, Resolve, Workspace};
+use cargo::util::{CargoResult, CargoResultExt, Cfg, Config};
+use std::collections::{BTreeMap, HashSet};
+use std::str::FromStr;
+
+/// A set of target platforms, along with the `cfg` values of each.
+pub(crate) struct Platforms {
+    targets: Vec<(String, Vec<Cfg>)>,
+}
+
+impl Platforms {
+    /// Asks `rustc` for the `cfg` values of each of `triples`.
+    pub(crate) fn new(triples: &[String], config: &Config) -> CargoResult<Platforms> {
+        let rustc = config.load_global_rustc(None)?;
+        let mut targets = Vec::new();
+        for triple in triples {
+            let output = rustc
+                .process()
+                .arg("--print=cfg")
+                .arg("--target")
+                .arg(triple)
+                .exec_with_output()
+                .chain_err(|| format!("failed to learn about target `{}`", triple))?;
+            let cfg = String::from_utf8(output.stdout)?
+                .lines()
+                .map(Cfg::from_str)
+                .collect::<CargoResult<Vec<_>>>()?;
+            targets.push((triple.clone(), cfg));
+        }
+        Ok(Platforms { targets })
+    }
+
+    /// Returns whether `dep` is used on any of the platforms, or if not the
+    /// platform it's restricted to.
+    fn check(&self, dep: &Dependency) -> Result<(), String> {
+        match dep.platform() {
+            Some(platform) => {
+                if self
+                    .targets
+                    .iter()
+                    .any(|(name, cfg)| platform.matches(name, Some(cfg)))
+                {
+                    Ok(())
+                } else {
+                    Err(format!("for `{}`", platform))
+                }
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+/// Walks the resolve graph from the members of `ws` along the dependency
+/// edges allowed by `platforms`, and which aren't dev-dependencies if
+/// `no_dev_deps` is set, returning every package which wasn't reached along
+/// with the reason why.
+pub(crate) fn unreachable_packages(
+    ws: &Workspace,
+    resolve: &Resolve,
+    platforms: Option<&Platforms>,
+    no_dev_deps: bool,
+) -> BTreeMap<PackageId, String> {
+    let check = |dep: &Dependency| {
+        if no_dev_deps && dep.kind() == Kind::Development {
+            return Err("as a dev-dependency".to_string());
+        }
+        match platforms {
+            Some(platforms) => platforms.check(dep),
+            None => Ok(()),
+        }
+    };
+
+    let mut reached = HashSet::new();
+    let mut reasons = BTreeMap::new();
+    let mut queue = ws.members().map(|p| p.package_id()).collect::<Vec<_>>();
+
+    while let Some(id) = queue.pop() {
+        if !reached.insert(id) {
+            continue;
+        }
+        for (dep_id, deps) in resolve.deps(id) {
+            // An edge may be declared several times, e.g. once per platform,
+            // and is followed if any of those declarations apply.
+            let mut why = Vec::new();
+            for dep in deps {
+                match check(dep) {
+                    Ok(()) => {
+                        why.clear();
+                        break;
+                    }
+                    Err(reason) => why.push(reason),
+                }
+            }
+            if why.is_empty() {
+                queue.push(dep_id);
+            } else {
+                reasons.entry(dep_id).or_insert_with(|| {
+                    format!("only required by `{}` {}", id.name(), why.join(", "))
+                });
+            }
+        }
+    }
+
+    resolve
+        .iter()
+        .filter(|id| !reached.contains(id))
+        .map(|id| {
+            let reason = reasons
+                .remove(&id)
+                .unwrap_or_else(|| "only required by other skipped packages".to_string());
+            (id, reason)
+        })
+        .collect()
+}
+
+/// Resolves `ws` again with only the features which are actually activated,
+/// returning every package of `resolve` which is no longer needed along with
+/// the reason why.
+///
+/// As with `cargo build`, `features` are enabled for the current package of
+/// the workspace while other members are built with their default features.
+pub(crate) fn inactive_packages(
+    ws: &Workspace,
+    resolve: &Resolve,
+    features: &[String],
+) -> CargoResult<BTreeMap<PackageId, String>> {
+    let features = Method::split_features(features);
+    let method = Method::Required {
+        dev_deps: true,
+        features: &features,
+        all_features: false,
+        uses_default_features: true,
+    };
+    let specs = ws
+        .members()
+        .map(|p| PackageIdSpec::from_package_id(p.package_id()))
+        .collect::<Vec<_>>();
+    let (_, active) = cargo::ops::resolve_ws_with_method(ws, None, method, &specs)
+        .chain_err(|| "failed to resolve activated features")?;
+    let active = active.iter().collect::<HashSet<_>>();
+
+    let mut reasons = BTreeMap::new();
+    for &id in active.iter() {
+        for (dep_id, deps) in resolve.deps(id) {
+            if !active.contains(&dep_id) && deps.iter().all(|d| d.is_optional()) {
+                reasons.entry(dep_id).or_insert_with(|| {
+                    format!(
+                        "optional dependency of `{}` which isn't activated",
+                        id.name()
+                    )
+                });
+            }
+        }
+    }
+
+    Ok(resolve
+        .iter()
+        .filter(|id| !active.contains(id))
+        .map(|id| {
+            let reason = reasons
+                .remove(&id)
+                .unwrap_or_else(|| "only required by other skipped packages".to_string());
+            (id, reason)
+        })
+        .collect())
+}
@@ -0,0 +1,159 @@
+//! Checks of the sources of git dependencies, which Cargo's listing of a
+//! package's files doesn't cover: the contents of submodules and files
+//! stored with Git LFS.
+
+use cargo::core::{Package, PackageId};
+use cargo::util::{CargoResult, CargoResultExt};
+use git2::Repository;
+use glob::Pattern;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// The first line of a Git LFS pointer file.
+const LFS_POINTER_PREFIX: &[u8] = b"version https://git-lfs.github.com/spec/v1";
+
+/// Pointer files are small, anything larger is real content.
+const LFS_POINTER_MAX_SIZE: u64 = 1024;
+
+/// The mode of an entry in a git index which is a submodule.
+const SUBMODULE_MODE: u32 = 0o160_000;
+
+/// Adds the files tracked by the submodules of the git checkout `pkg` is in
+/// to `paths`, which `pkg` includes according to its `include` and `exclude`
+/// rules.
+///
+/// Cargo checks out submodules along with a git dependency, but doesn't list
+/// their files if they contain a `Cargo.toml`, as it considers them to be a
+/// separate package. A submodule which isn't checked out is an error.
+pub(crate) fn add_submodule_files(pkg: &Package, paths: &mut Vec<PathBuf>) -> CargoResult<()> {
+    let id = pkg.package_id();
+    let root = pkg.root();
+    let repo = match Repository::discover(root) {
+        Ok(repo) => repo,
+        Err(_) => return Ok(()),
+    };
+    let rules = PackageRules::new(pkg)?;
+    submodule_files(id, &repo, root, &rules, paths)
+        .chain_err(|| format!("failed to list the submodules of {}", id))?;
+    paths.sort();
+    paths.dedup();
+    Ok(())
+}
+
+fn submodule_files(
+    id: PackageId,
+    repo: &Repository,
+    root: &Path,
+    rules: &PackageRules,
+    paths: &mut Vec<PathBuf>,
+) -> CargoResult<()> {
+    let workdir = match repo.workdir() {
+        Some(workdir) => workdir,
+        None => return Ok(()),
+    };
+    for submodule in repo.submodules()? {
+        let path = workdir.join(submodule.path());
+        if !path.starts_with(root) {
+            continue;
+        }
+        let sub = submodule.open().map_err(|_| {
+            failure::format_err!(
+                "submodule `{}` of {} isn't checked out",
+                path.strip_prefix(root).unwrap().display(),
+                id
+            )
+        })?;
+        for entry in sub.index()?.iter() {
+            if entry.mode == SUBMODULE_MODE {
+                continue;
+            }
+            let relative = std::str::from_utf8(&entry.path)
+                .chain_err(|| format!("invalid utf-8 filename in `{}`", path.display()))?;
+            let file = path.join(relative);
+            if file.is_file() && rules.is_included(file.strip_prefix(root).unwrap()) {
+                paths.push(file);
+            }
+        }
+        submodule_files(id, &sub, root, rules, paths)?;
+    }
+    Ok(())
+}
+
+/// The `include` and `exclude` rules of a package's manifest.
+struct PackageRules {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+impl PackageRules {
+    fn new(pkg: &Package) -> CargoResult<PackageRules> {
+        let parse = |rules: &[String]| -> CargoResult<Vec<Pattern>> {
+            let mut patterns = Vec::new();
+            for rule in rules {
+                let p = Pattern::new(rule.trim_start_matches('/'))
+                    .chain_err(|| format!("invalid pattern `{}` of {}", rule, pkg.package_id()))?;
+                patterns.push(p);
+            }
+            Ok(patterns)
+        };
+        Ok(PackageRules {
+            include: parse(pkg.manifest().include())?,
+            exclude: parse(pkg.manifest().exclude())?,
+        })
+    }
+
+    /// Returns whether the file at `relative` in the package is included.
+    ///
+    /// A rule matching a directory applies to everything in it, as with the
+    /// gitignore-style matching Cargo is moving to.
+    fn is_included(&self, relative: &Path) -> bool {
+        let matches = |patterns: &[Pattern]| {
+            relative
+                .ancestors()
+                .filter(|path| !path.as_os_str().is_empty())
+                .any(|path| patterns.iter().any(|p| p.matches_path(path)))
+        };
+        if self.include.is_empty() {
+            !matches(&self.exclude)
+        } else {
+            matches(&self.include)
+        }
+    }
+}
+
+/// Fails if any of `paths` of the package `id` in `root` is a Git LFS pointer
+/// rather than the content it points to, which Cargo doesn't fetch.
+pub(crate) fn check_lfs_pointers(id: PackageId, root: &Path, paths: &[PathBuf]) -> CargoResult<()> {
+    let mut pointers = Vec::new();
+    for path in paths {
+        if is_lfs_pointer(path).chain_err(|| format!("failed to read `{}`", path.display()))? {
+            pointers.push(path.strip_prefix(root).unwrap_or(path));
+        }
+    }
+    if pointers.is_empty() {
+        return Ok(());
+    }
+    let list = pointers
+        .iter()
+        .map(|p| format!("\n  {}", p.display()))
+        .collect::<String>();
+    failure::bail!(
+        "{} contains Git LFS pointer files instead of their contents, which \
+         Cargo doesn't fetch:{}\n\
+         Files which aren't needed to build it can be left out with `--strip`",
+        id,
+        list
+    )
+}
+
+fn is_lfs_pointer(path: &Path) -> CargoResult<bool> {
+    let file = File::open(path)?;
+    if file.metadata()?.len() > LFS_POINTER_MAX_SIZE {
+        return Ok(false);
+    }
+    let mut start = Vec::new();
+    file.take(LFS_POINTER_PREFIX.len() as u64)
+        .read_to_end(&mut start)?;
+    Ok(start == LFS_POINTER_PREFIX)
+}
@@ -0,0 +1,139 @@
+//! The `.cargo-vendor-info.json` file placed next to the checksums of every
+//! crate vendored as a directory, recording where the crate came from.
+//!
+//! Unlike `.cargo-checksum.json`, which Cargo reads, this file is only meant
+//! for people and tools auditing a vendor directory, so a crate can be traced
+//! back to its origin without the lock file it was vendored for. It isn't
+//! listed in the crate's checksums.
+
+use crate::relative_path;
+use cargo::core::{GitReference, Package};
+use cargo::util::{CargoResult, CargoResultExt};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the provenance file in the directory of a vendored crate.
+pub(crate) const VENDOR_INFO_FILE_NAME: &str = ".cargo-vendor-info.json";
+
+/// Name of the file Cargo adds to published crates with the commit they
+/// were published from.
+const VCS_INFO_FILE_NAME: &str = ".cargo_vcs_info.json";
+
+#[derive(Serialize, Deserialize, PartialEq)]
+pub(crate) struct VendorInfo {
+    pub(crate) name: String,
+    pub(crate) version: String,
+    /// The full source id, including the locked commit of git sources.
+    pub(crate) source: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    registry: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    git: Option<GitInfo>,
+    /// Checksum of the package recorded in the lock file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    checksum: Option<String>,
+    /// The lock files which required the crate, relative to the directory
+    /// `cargo vendor` was run in.
+    lockfiles: Vec<PathBuf>,
+    /// The contents of `.cargo_vcs_info.json` of a published crate.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    vcs_info: Option<serde_json::Value>,
+    vendored_by: String,
+}
+
+#[derive(Serialize, Deserialize, PartialEq)]
+struct GitInfo {
+    url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    branch: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rev: Option<String>,
+    /// The exact commit, whichever reference was asked for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    commit: Option<String>,
+}
+
+impl VendorInfo {
+    /// Describes `pkg`, vendored with the lock file `checksum` for the lock
+    /// files `lockfiles` from the directory `cwd`.
+    pub(crate) fn new(
+        pkg: &Package,
+        checksum: Option<&str>,
+        lockfiles: &BTreeSet<PathBuf>,
+        cwd: &Path,
+    ) -> CargoResult<VendorInfo> {
+        let id = pkg.package_id();
+        let source = id.source_id();
+        let registry = if source.is_registry() {
+            Some(source.url().to_string())
+        } else {
+            None
+        };
+        let git = if source.is_git() {
+            let mut info = GitInfo {
+                url: source.url().to_string(),
+                branch: None,
+                tag: None,
+                rev: None,
+                commit: source.precise().map(|s| s.to_string()),
+            };
+            match source.git_reference() {
+                Some(GitReference::Branch(b)) => info.branch = Some(b.clone()),
+                Some(GitReference::Tag(t)) => info.tag = Some(t.clone()),
+                Some(GitReference::Rev(r)) => info.rev = Some(r.clone()),
+                None => {}
+            }
+            Some(info)
+        } else {
+            None
+        };
+        let vcs_info_path = pkg.root().join(VCS_INFO_FILE_NAME);
+        let vcs_info = if vcs_info_path.is_file() {
+            let contents = fs::read(&vcs_info_path)?;
+            let value = serde_json::from_slice(&contents)
+                .chain_err(|| format!("failed to parse `{}`", vcs_info_path.display()))?;
+            Some(value)
+        } else {
+            None
+        };
+        Ok(VendorInfo {
+            name: id.name().to_string(),
+            version: id.version().to_string(),
+            source: source.to_url().to_string(),
+            registry,
+            git,
+            checksum: checksum.map(|s| s.to_string()),
+            lockfiles: lockfiles.iter().map(|l| relative_path(cwd, l)).collect(),
+            vcs_info,
+            vendored_by: format!("cargo-vendor {}", env!("CARGO_PKG_VERSION")),
+        })
+    }
+
+    /// Reads the provenance of the crate vendored in `dir`, if it's recorded.
+    pub(crate) fn read(dir: &Path) -> CargoResult<Option<VendorInfo>> {
+        let path = dir.join(VENDOR_INFO_FILE_NAME);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let info = serde_json::from_slice(&fs::read(&path)?)
+            .chain_err(|| format!("failed to parse `{}`", path.display()))?;
+        Ok(Some(info))
+    }
+
+    /// Writes the provenance into the crate vendored in `dir`, unless it's
+    /// already recorded there.
+    pub(crate) fn write(&self, dir: &Path) -> CargoResult<()> {
+        if VendorInfo::read(dir).ok().and_then(|i| i).as_ref() == Some(self) {
+            return Ok(());
+        }
+        let path = dir.join(VENDOR_INFO_FILE_NAME);
+        let mut contents = serde_json::to_string_pretty(self)?;
+        contents.push('\n');
+        fs::write(&path, contents).chain_err(|| format!("failed to write `{}`", path.display()))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,1827 @@
+//! Vendor all dependencies of a set of Cargo workspaces onto the local
+//! filesystem.
+//!
+//! This is the library half of the `cargo vendor` subcommand. The main entry
+//! point is the [`vendor`] function which is configured through
+//! [`VendorOptions`]:
+//!
+//! ```no_run
+//! use cargo::core::Workspace;
+//! use cargo::util::Config;
+//! use cargo_vendor::VendorOptions;
+//!
+//! # fn main() -> cargo::util::CargoResult<()> {
+//! let config = Config::default()?;
+//! let ws = Workspace::new(&config.cwd().join("Cargo.toml"), &config)?;
+//! let mut options = VendorOptions::new("vendor");
+//! options.explicit_version(true);
+//! let vendored = cargo_vendor::vendor(&[ws], &options, &config)?;
+//! println!("{}", toml::to_string(&vendored.config).unwrap());
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Note that the `Config` passed in should not have any `[source]`
+//! replacement configured, otherwise the vendored sources would be read back
+//! from the previous vendor directory rather than from the original source.
+
+use crate::cargo_config::{MERGED_SOURCE_NAME, SEPARATE_SOURCE_PREFIX};
+use crate::dir_format::DirFormat;
+use crate::events::Event;
+use crate::filter::Platforms;
+use crate::info::VendorInfo;
+use crate::patch::Patches;
+use crate::policy::Policy;
+use crate::strip::{Strip, StrippedTarget};
+use cargo::core::{GitReference, Package, PackageId, SourceId, Workspace};
+use cargo::util::Sha256;
+use cargo::util::{CargoResult, CargoResultExt, Config};
+use failure::bail;
+use glob::Pattern;
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::collections::btree_map::Entry;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::{self, File};
+use std::hash::Hasher;
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+mod audit;
+mod cargo_config;
+mod copy;
+mod dedup;
+mod diff;
+mod dir_format;
+mod events;
+mod filter;
+mod git;
+mod info;
+mod local_registry;
+mod manifest;
+mod notices;
+mod patch;
+mod policy;
+mod sbom;
+mod strip;
+mod verify;
+
+pub use crate::audit::{audit, read_audit_ignores, AuditFinding, AuditIgnore, AuditReport};
+pub use crate::cargo_config::write_config;
+pub use crate::copy::LinkMode;
+pub use crate::dedup::{DedupMode, DedupReport};
+pub use crate::diff::{ChangeKind, CrateChange, FileChanges, VendorDiff};
+pub use crate::events::MessageFormat;
+pub use crate::notices::{write_notices, NoticesFormat};
+pub use crate::sbom::{write_sbom, SbomFormat};
+pub use crate::verify::{verify, CrateVerification, VerifyReport};
+
+/// Options controlling how [`vendor`] lays out the vendor directory.
+#[derive(Clone, Debug)]
+pub struct VendorOptions {
+    destination: PathBuf,
+    explicit_version: bool,
+    dir_format: Option<String>,
+    no_delete: bool,
+    disallow_duplicates: bool,
+    relative_path: bool,
+    only_git_deps: bool,
+    merge_sources: bool,
+    vendor_main_crate: bool,
+    include_path_deps: Vec<String>,
+    format: VendorFormat,
+    platforms: Vec<String>,
+    prune_optional_deps: bool,
+    features: Vec<String>,
+    no_dev_deps: bool,
+    strip: Vec<String>,
+    patches: Option<PathBuf>,
+    policy: Option<PathBuf>,
+    branch_deps: BranchDeps,
+    message_format: MessageFormat,
+    link_mode: LinkMode,
+    dedup: Option<DedupMode>,
+    diff: bool,
+}
+
+/// The kind of source the vendor directory is laid out as.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VendorFormat {
+    /// A `directory` source with one unpacked directory per crate.
+    Directory,
+    /// A `local-registry` source with the `.crate` file of each crate and an
+    /// index describing them. Only registry dependencies can be vendored in
+    /// this format.
+    LocalRegistry,
+}
+
+/// What to do about vendored git dependencies which are only pinned to a
+/// branch, see [`VendorOptions::branch_deps`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BranchDeps {
+    /// Vendor them like any other dependency.
+    Allow,
+    /// Print a warning for each of them.
+    Warn,
+    /// Fail before anything is written, listing all of them.
+    Deny,
+}
+
+/// The `[source]` configuration needed to use a vendor directory.
+#[derive(Serialize)]
+pub struct VendorConfig {
+    pub source: BTreeMap<String, VendorSource>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase", untagged)]
+pub enum VendorSource {
+    Directory {
+        directory: PathBuf,
+    },
+    LocalRegistry {
+        #[serde(rename = "local-registry")]
+        local_registry: PathBuf,
+    },
+    Registry {
+        registry: Option<String>,
+        #[serde(rename = "replace-with")]
+        replace_with: String,
+    },
+    /// The replacement of a git source, with the same reference as the
+    /// dependency since Cargo only replaces an exactly matching source.
+    ///
+    /// The commit locked for a branch isn't part of the configuration, it's
+    /// recorded as `git.commit` in each crate's `.cargo-vendor-info.json`.
+    Git {
+        git: String,
+        branch: Option<String>,
+        tag: Option<String>,
+        rev: Option<String>,
+        #[serde(rename = "replace-with")]
+        replace_with: String,
+    },
+}
+
+/// The result of a successful call to [`vendor`].
+pub struct Vendored {
+    /// Configuration to place in `.cargo/config` to use the vendored sources.
+    pub config: VendorConfig,
+    /// Every package which now lives in the vendor directory, sorted by
+    /// package id.
+    pub packages: Vec<VendoredPackage>,
+    /// Packages in the resolve graph which were deliberately not vendored,
+    /// sorted by package id.
+    pub skipped: Vec<SkippedPackage>,
+    /// How the contents of the vendor directory changed, if
+    /// [`VendorOptions::diff`] was set. Only crates vendored as directories
+    /// are compared, not local registries.
+    pub diff: Option<VendorDiff>,
+    /// The files replaced with links, if deduplication was enabled.
+    pub dedup: Option<DedupReport>,
+    /// The original directory of every path dependency vendored with
+    /// [`VendorOptions::include_path_deps`], and the path of its copy which
+    /// dependencies on it have to use to build without the original. The
+    /// copy's path is relative if [`VendorOptions::relative_path`] is set.
+    pub path_dependencies: BTreeMap<PathBuf, PathBuf>,
+}
+
+/// A package which was left out of the vendor directory.
+pub struct SkippedPackage {
+    pub id: PackageId,
+    /// Human readable explanation of why the package isn't needed.
+    pub reason: String,
+}
+
+/// A single package placed in the vendor directory.
+pub struct VendoredPackage {
+    pub id: PackageId,
+    /// Directory the package's sources were copied to.
+    pub path: PathBuf,
+    /// The package as read from its original source.
+    pub package: Package,
+    /// Checksum of the package recorded in the lock file, if any.
+    pub checksum: Option<String>,
+    /// Other vendored packages this one depends on, sorted by package id.
+    pub dependencies: Vec<PackageId>,
+}
+
+const SOURCES_FILE_NAME: &str = ".sources";
+const CHECKSUM_FILE_NAME: &str = ".cargo-checksum.json";
+const STUB_FILE_NAME: &str = ".cargo-vendor-stub";
+
+impl VendorOptions {
+    /// Creates a new set of options which vendors into `destination`, which
+    /// is interpreted relative to the current directory of the `Config`.
+    pub fn new<P: Into<PathBuf>>(destination: P) -> VendorOptions {
+        VendorOptions {
+            destination: destination.into(),
+            explicit_version: false,
+            dir_format: None,
+            no_delete: false,
+            disallow_duplicates: false,
+            relative_path: false,
+            only_git_deps: false,
+            merge_sources: true,
+            vendor_main_crate: false,
+            include_path_deps: Vec::new(),
+            format: VendorFormat::Directory,
+            platforms: Vec::new(),
+            prune_optional_deps: false,
+            features: Vec::new(),
+            no_dev_deps: false,
+            strip: Vec::new(),
+            patches: None,
+            policy: None,
+            branch_deps: BranchDeps::Allow,
+            message_format: MessageFormat::Human,
+            link_mode: LinkMode::Copy,
+            dedup: None,
+            diff: false,
+        }
+    }
+
+    /// Always include the version in the name of a crate's directory.
+    pub fn explicit_version(&mut self, explicit_version: bool) -> &mut VendorOptions {
+        self.explicit_version = explicit_version;
+        self
+    }
+
+    /// Names the directory of each crate after `template` instead, like
+    /// `{name}-{version}`, overriding [`VendorOptions::explicit_version`].
+    ///
+    /// The placeholders are `{name}`, `{version}`, `{source}` for the kind of
+    /// source (`registry`, `git` or `path`), `{rev}` for the abbreviated
+    /// commit of a git source, `{registry}` for the name of a registry and
+    /// `{hash}` for a hash of the source. Components before the last `/`,
+    /// like `{source}-{hash}` in `{source}-{hash}/{name}-{version}`, name the
+    /// directory of each source instead of the crate, so they can only use
+    /// `{source}`, `{registry}` and `{hash}` and need
+    /// [`VendorOptions::merge_sources`] to be disabled. Without `{hash}` two
+    /// git repositories, or two registries without a name, usually end up in
+    /// the same directory. Templates which would place two
+    /// crates or two sources into the same directory are an error before
+    /// anything is written.
+    pub fn dir_format<S: Into<String>>(&mut self, template: S) -> &mut VendorOptions {
+        self.dir_format = Some(template.into());
+        self
+    }
+
+    /// Don't delete crates in the vendor directory which are no longer used.
+    pub fn no_delete(&mut self, no_delete: bool) -> &mut VendorOptions {
+        self.no_delete = no_delete;
+        self
+    }
+
+    /// Fail if two versions of one crate would be vendored.
+    pub fn disallow_duplicates(&mut self, disallow_duplicates: bool) -> &mut VendorOptions {
+        self.disallow_duplicates = disallow_duplicates;
+        self
+    }
+
+    /// Emit the destination as given, rather than as an absolute path, in
+    /// the generated configuration.
+    pub fn relative_path(&mut self, relative_path: bool) -> &mut VendorOptions {
+        self.relative_path = relative_path;
+        self
+    }
+
+    /// Only vendor git dependencies, not registry dependencies.
+    pub fn only_git_deps(&mut self, only_git_deps: bool) -> &mut VendorOptions {
+        self.only_git_deps = only_git_deps;
+        self
+    }
+
+    /// Vendor all sources into one directory (the default) rather than into
+    /// one subdirectory per source.
+    pub fn merge_sources(&mut self, merge_sources: bool) -> &mut VendorOptions {
+        self.merge_sources = merge_sources;
+        self
+    }
+
+    /// Vendor the main crate of each workspace in addition to its
+    /// dependencies.
+    pub fn vendor_main_crate(&mut self, vendor_main_crate: bool) -> &mut VendorOptions {
+        self.vendor_main_crate = vendor_main_crate;
+        self
+    }
+
+    /// Vendor the path dependencies living outside of every workspace whose
+    /// name matches the glob `pattern`, e.g. `*` for all of them.
+    ///
+    /// Path dependencies between the vendored copies are pointed at each
+    /// other. The workspaces still refer to the original directories, see
+    /// [`Vendored::path_dependencies`] for where they should point instead.
+    /// This may be called multiple times.
+    pub fn include_path_deps<S: Into<String>>(&mut self, pattern: S) -> &mut VendorOptions {
+        self.include_path_deps.push(pattern.into());
+        self
+    }
+
+    /// Selects the kind of source to produce, a directory source by default.
+    pub fn format(&mut self, format: VendorFormat) -> &mut VendorOptions {
+        self.format = format;
+        self
+    }
+
+    /// Only vendor packages used when building for the target `triple`.
+    ///
+    /// This may be called multiple times to vendor for several platforms. If
+    /// it's never called packages for all platforms are vendored. Packages
+    /// which aren't needed are still written as stubs with only their
+    /// manifest and empty source files, as Cargo requires every package in
+    /// the lock file to be present. Note that build dependencies are also
+    /// filtered by these platforms, so the host platform should be included
+    /// if it's not one of the targets.
+    pub fn platform<S: Into<String>>(&mut self, triple: S) -> &mut VendorOptions {
+        self.platforms.push(triple.into());
+        self
+    }
+
+    /// Only vendor optional dependencies which are activated by the features
+    /// the workspace members are built with, by default their default
+    /// features. Like other skipped packages the rest are written as stubs.
+    pub fn prune_optional_deps(&mut self, prune_optional_deps: bool) -> &mut VendorOptions {
+        self.prune_optional_deps = prune_optional_deps;
+        self
+    }
+
+    /// Enables `feature` of the current package of each workspace, in
+    /// addition to its default features, when deciding which optional
+    /// dependencies to vendor. This implies `prune_optional_deps`.
+    ///
+    /// As with `cargo build --features` this may also be a space or comma
+    /// separated list, or a feature of a dependency such as `foo/bar`.
+    pub fn feature<S: Into<String>>(&mut self, feature: S) -> &mut VendorOptions {
+        self.features.push(feature.into());
+        self
+    }
+
+    /// Don't vendor packages which are only used as dev-dependencies, either
+    /// of the workspace members or of other packages. Like other skipped
+    /// packages these are written as stubs, and the dev-dependencies are
+    /// removed from the `Cargo.toml` of every vendored crate.
+    pub fn no_dev_deps(&mut self, no_dev_deps: bool) -> &mut VendorOptions {
+        self.no_dev_deps = no_dev_deps;
+        self
+    }
+
+    /// Leave files matching the glob `pattern` out of every vendored crate,
+    /// for example `tests` or `**/*.png`.
+    ///
+    /// Patterns are matched against paths relative to the root of the crate,
+    /// and matching a directory strips everything inside of it. Test, bench
+    /// and example targets whose source is stripped are removed from the
+    /// crate's `Cargo.toml`, while stripping the source of any other target
+    /// is an error. This may be called multiple times, and can't be used
+    /// with the local registry format.
+    pub fn strip<S: Into<String>>(&mut self, pattern: S) -> &mut VendorOptions {
+        self.strip.push(pattern.into());
+        self
+    }
+
+    /// Apply the patches in `dir` to the vendored crates, relative to the
+    /// current directory of the `Config`.
+    ///
+    /// The patches for a crate are placed in `<dir>/<crate>-<version>/` and
+    /// applied in order of their file names with `patch -p1`, after which
+    /// the crate's checksums are recomputed. A patch which doesn't apply,
+    /// or patches for a different version of a crate than is vendored, are
+    /// an error. Patches can't be used with the local registry format.
+    pub fn patches<P: Into<PathBuf>>(&mut self, dir: P) -> &mut VendorOptions {
+        self.patches = Some(dir.into());
+        self
+    }
+
+    /// Check the vendored crates against the policy file at `path`, relative
+    /// to the current directory of the `Config`, before anything is written.
+    ///
+    /// The policy can deny crates or some of their versions, and restrict
+    /// the licenses and sources of crates, with exceptions for single
+    /// crates. Every violation is reported in one error. Stubs of packages
+    /// which aren't needed aren't checked, and path dependencies don't need
+    /// an allowed source.
+    pub fn policy<P: Into<PathBuf>>(&mut self, path: P) -> &mut VendorOptions {
+        self.policy = Some(path.into());
+        self
+    }
+
+    /// Selects what to do about git dependencies which are only pinned to a
+    /// branch, allowing them by default.
+    ///
+    /// The lock file still pins them to a commit, but updating it moves them
+    /// to whatever the branch points to then, which can't be reproduced
+    /// later. Stubs of packages which aren't needed aren't checked.
+    pub fn branch_deps(&mut self, mode: BranchDeps) -> &mut VendorOptions {
+        self.branch_deps = mode;
+        self
+    }
+
+    /// Selects how progress is reported, human readable status lines by
+    /// default.
+    pub fn message_format(&mut self, message_format: MessageFormat) -> &mut VendorOptions {
+        self.message_format = message_format;
+        self
+    }
+
+    /// Selects how files are placed into the vendor directory, copying them
+    /// by default.
+    ///
+    /// Crates which are patched are always copied, so patching them can't
+    /// change Cargo's cache.
+    pub fn link_mode(&mut self, link_mode: LinkMode) -> &mut VendorOptions {
+        self.link_mode = link_mode;
+        self
+    }
+
+    /// Replaces files which are identical to a file in another vendored
+    /// crate, or elsewhere in the same crate, with links to it.
+    pub fn dedup(&mut self, mode: DedupMode) -> &mut VendorOptions {
+        self.dedup = Some(mode);
+        self
+    }
+
+    /// Compares the contents of the vendor directory before and after
+    /// vendoring, reported in [`Vendored::diff`].
+    pub fn diff(&mut self, diff: bool) -> &mut VendorOptions {
+        self.diff = diff;
+        self
+    }
+}
+
+/// Vendors all dependencies of `workspaces` into the destination configured
+/// in `options`.
+pub fn vendor(
+    workspaces: &[Workspace],
+    options: &VendorOptions,
+    config: &Config,
+) -> CargoResult<Vendored> {
+    let path = &config.cwd().join(&options.destination);
+    let before = if options.diff {
+        let snapshot = diff::Snapshot::read(path)
+            .chain_err(|| format!("failed to read the contents of `{}`", path.display()))?;
+        Some(snapshot)
+    } else {
+        None
+    };
+
+    let mut vendored = sync(workspaces, options, path, config).chain_err(|| "failed to sync")?;
+    if let Some(mode) = options.dedup {
+        let report = dedup::dedup(&vendored, mode).chain_err(|| "failed to deduplicate files")?;
+        let event = Event::Dedup {
+            files: report.files,
+            bytes: report.bytes,
+        };
+        events::emit(options.message_format, config, event)?;
+        vendored.dedup = Some(report);
+    }
+
+    if let Some(before) = before {
+        let mut after = diff::Snapshot::read(path)?;
+        after.set_sources(&vendored);
+        vendored.diff = Some(diff::diff(&before, &after));
+    }
+    Ok(vendored)
+}
+
+/// Vendors all dependencies of `workspaces` into `local_dst`, the
+/// destination resolved against the current directory of `config`.
+fn sync(
+    workspaces: &[Workspace],
+    options: &VendorOptions,
+    local_dst: &Path,
+    config: &Config,
+) -> CargoResult<Vendored> {
+    let merge_sources = options.merge_sources;
+    let mut canonical_local_dst = local_dst
+        .canonicalize()
+        .unwrap_or_else(|_| local_dst.to_path_buf());
+
+    let Collected {
+        ids,
+        checksums,
+        dependencies,
+        lockfiles,
+        mut added_crates,
+        skipped,
+        path_deps,
+    } = collect_packages(workspaces, options, &canonical_local_dst, config)?;
+    let versions = check_versions(&ids, merge_sources)?;
+
+    if options.format == VendorFormat::LocalRegistry {
+        if options.only_git_deps {
+            bail!("git dependencies cannot be vendored into a local registry");
+        }
+        if !options.strip.is_empty() {
+            bail!("files cannot be stripped from crates in a local registry");
+        }
+        if options.patches.is_some() {
+            bail!("crates in a local registry cannot be patched");
+        }
+        if options.dedup.is_some() {
+            bail!("files of crates in a local registry cannot be deduplicated");
+        }
+        if options.dir_format.is_some() {
+            bail!("the files of a local registry cannot be named with a directory format");
+        }
+        if let Some(id) = ids.keys().find(|id| !id.source_id().is_registry()) {
+            bail!(
+                "only registry dependencies can be vendored into a local \
+                 registry, but `{}` is from `{}`",
+                id,
+                id.source_id()
+            );
+        }
+    }
+
+    let strip = Strip::new(&options.strip)?;
+    let patches_dir = options.patches.as_ref().map(|p| config.cwd().join(p));
+    let patches = Patches::new(
+        patches_dir.as_ref().map(|p| p.as_path()),
+        ids.keys(),
+        config,
+    )?;
+    let stubs = skipped.iter().map(|p| p.id).collect::<HashSet<_>>();
+
+    if let Some(path) = &options.policy {
+        let policy = Policy::read(&config.cwd().join(path))?;
+        policy.check(
+            ids.values()
+                .filter(|pkg| !stubs.contains(&pkg.package_id()))
+                .filter(|pkg| pkg.package_id().source_id().is_git() || !options.only_git_deps),
+        )?;
+    }
+    check_branch_deps(
+        ids.keys().filter(|id| !stubs.contains(id)).cloned(),
+        options.branch_deps,
+        config,
+    )?;
+
+    let registry_names = registry_names(config)?;
+    let layout = plan_layout(&ids, &versions, options, &registry_names)?;
+
+    // Nothing has been written to the vendor directory up to here
+    prepare_destination(options, local_dst)?;
+    canonical_local_dst = local_dst.canonicalize()?;
+    let existing_crates = existing_crates(&canonical_local_dst, merge_sources)?;
+    if !merge_sources {
+        for dir in layout.sources.values() {
+            let source_dir = canonical_local_dst.join(dir);
+            fs::create_dir_all(&source_dir)
+                .chain_err(|| format!("failed to create: `{}`", source_dir.display()))?;
+        }
+    }
+
+    let mut vendored = Vec::new();
+    let mut registries = BTreeMap::new();
+    let mut pending = Vec::new();
+    let mut stale_stubs = Vec::new();
+    for (id, pkg) in ids.iter() {
+        // Next up, copy it to the vendor directory
+        let dst_name = match layout.crates.get(id) {
+            Some(dst_name) => dst_name,
+            // Skip out if we only want to process git dependencies
+            None => continue,
+        };
+        let source_dir = if merge_sources {
+            canonical_local_dst.clone()
+        } else {
+            canonical_local_dst.join(&layout.sources[&id.source_id()])
+        };
+        let checksum = checksums[id].as_ref().map(|s| s.as_str());
+
+        if options.format == VendorFormat::LocalRegistry {
+            // Packages which aren't needed only have to be in the index for
+            // Cargo's resolver, their `.crate` file is never unpacked.
+            if stubs.contains(id) {
+                registries
+                    .entry(source_dir)
+                    .or_insert_with(Vec::new)
+                    .push((pkg, checksum.unwrap_or("").to_string()));
+                continue;
+            }
+            let (dst, cksum) = local_registry::copy_crate_file(
+                *id,
+                checksum,
+                &source_dir,
+                options.link_mode,
+                options.message_format,
+                config,
+            )?;
+            added_crates.push(dst.clone());
+            vendored.push(VendoredPackage {
+                id: *id,
+                path: dst,
+                package: pkg.clone(),
+                checksum: Some(cksum.clone()),
+                dependencies: dependencies[id].iter().cloned().collect(),
+            });
+            registries
+                .entry(source_dir)
+                .or_insert_with(Vec::new)
+                .push((pkg, cksum));
+            continue;
+        }
+
+        let dst = source_dir.join(dst_name);
+        added_crates.push(dst.clone());
+
+        // Crates which were already vendored from the same package with the
+        // same options are left alone, to keep no-op runs fast.
+        let stub = stubs.contains(id);
+        let patch_dir = patches.get(*id);
+        let fingerprint = fingerprint(options, patch_dir)?;
+        let links = is_fresh(*id, checksum, &dst, stub, &fingerprint);
+        let fresh = links.is_some();
+
+        let link_mode = if patch_dir.is_some() {
+            LinkMode::Copy
+        } else {
+            options.link_mode
+        };
+        let p = Pending {
+            pkg,
+            dst: dst.clone(),
+            checksum,
+            fingerprint,
+            patch_dir,
+            link_mode,
+            links: links.unwrap_or_default(),
+            stripped_targets: Vec::new(),
+            fresh,
+        };
+        if stub && !fresh {
+            stale_stubs.push(p);
+            continue;
+        }
+        pending.push(p);
+        if stub {
+            continue;
+        }
+
+        vendored.push(VendoredPackage {
+            id: *id,
+            path: dst.clone(),
+            package: pkg.clone(),
+            checksum: checksum.map(|s| s.to_string()),
+            dependencies: dependencies[id].iter().cloned().collect(),
+        });
+    }
+
+    check_links(&mut pending);
+    for p in pending.iter_mut().filter(|p| !p.fresh) {
+        p.stripped_targets = strip.stripped_targets(p.pkg)?;
+    }
+    let stale = pending
+        .iter()
+        .chain(stale_stubs.iter())
+        .filter(|p| !p.fresh)
+        .map(|p| p.pkg)
+        .collect::<Vec<_>>();
+    clear_registry_sources(workspaces, &stale)?;
+    for p in stale_stubs {
+        let files = vendor_stub(p.pkg, &p.dst, options, &strip)?;
+        write_checksums(
+            p.pkg.package_id(),
+            p.checksum,
+            &p.dst,
+            &p.fingerprint,
+            &files,
+        )?;
+    }
+    let jobs = pending
+        .iter()
+        .filter(|p| !p.fresh)
+        .map(|p| copy_job(p.pkg, &p.dst, &strip, p.link_mode, config))
+        .collect::<CargoResult<Vec<_>>>()?;
+
+    // Where the path dependencies moved to, by their original directory
+    let moved = pending
+        .iter()
+        .filter(|p| path_deps.contains(&p.pkg.package_id()))
+        .map(|p| Ok((p.pkg.root().canonicalize()?, p.dst.clone())))
+        .collect::<CargoResult<HashMap<_, _>>>()?;
+
+    // The files of all crates are copied at once, and everything else is
+    // done afterwards in order to keep the output the same on every run.
+    let mut copied = copy::cp_sources(&jobs)?.into_iter();
+    for p in pending {
+        let id = p.pkg.package_id();
+        if p.fresh {
+            let event = Event::Fresh { id, path: &p.dst };
+            events::emit(options.message_format, config, event)?;
+        } else {
+            let files = copied.next().unwrap();
+            let files = finish_directory(&p, files, &moved, options, config)?;
+            write_checksums(id, p.checksum, &p.dst, &p.fingerprint, &files)?;
+        }
+        if !stubs.contains(&id) {
+            let info = VendorInfo::new(p.pkg, p.checksum, &lockfiles[&id], config.cwd())?;
+            info.write(&p.dst)?;
+        }
+    }
+
+    for (root, packages) in registries {
+        local_registry::write_index(&root, &packages, options.no_dev_deps, config)?;
+    }
+
+    if !options.no_delete {
+        for path in existing_crates {
+            if !added_crates.contains(&path) {
+                events::emit(
+                    options.message_format,
+                    config,
+                    Event::Delete { path: &path },
+                )?;
+                if path.is_dir() {
+                    fs::remove_dir_all(&path)?;
+                } else {
+                    fs::remove_file(&path)?;
+                }
+            }
+        }
+    }
+
+    if !merge_sources {
+        update_sources_file(&canonical_local_dst, &layout.sources)?;
+    }
+
+    // Only the relationships between vendored packages are of interest
+    let vendored_ids = vendored.iter().map(|p| p.id).collect::<HashSet<_>>();
+    for pkg in vendored.iter_mut() {
+        pkg.dependencies.retain(|id| vendored_ids.contains(id));
+    }
+
+    let dir = config_dir(options, config);
+    let path_dependencies = moved
+        .into_iter()
+        .map(|(src, dst)| {
+            (
+                src,
+                dir.join(dst.strip_prefix(&canonical_local_dst).unwrap()),
+            )
+        })
+        .collect();
+
+    Ok(Vendored {
+        config: vendor_config(&layout.sources, &registry_names, options, config),
+        packages: vendored,
+        skipped,
+        diff: None,
+        dedup: None,
+        path_dependencies,
+    })
+}
+
+/// Packages found in the resolve graphs of all workspaces.
+struct Collected {
+    /// Packages to vendor.
+    ids: BTreeMap<PackageId, Package>,
+    /// Checksum recorded in the lock file for each of `ids`.
+    checksums: HashMap<PackageId, Option<String>>,
+    /// Dependencies of each of `ids` in the resolve graphs of the workspaces.
+    dependencies: HashMap<PackageId, BTreeSet<PackageId>>,
+    /// The lock files of the workspaces each of `ids` is in.
+    lockfiles: HashMap<PackageId, BTreeSet<PathBuf>>,
+    /// Path dependencies which live inside the vendor directory, and so must
+    /// not be deleted from it.
+    added_crates: Vec<PathBuf>,
+    /// Packages which aren't needed by any workspace. These are also in
+    /// `ids` as they're still vendored as stubs.
+    skipped: Vec<SkippedPackage>,
+    /// Path dependencies outside of the workspaces which are vendored.
+    path_deps: HashSet<PackageId>,
+}
+
+/// Attempts to work around rust-lang/cargo#5956 for the `stale` packages,
+/// which are about to be copied.
+fn clear_registry_sources(workspaces: &[Workspace], stale: &[&Package]) -> CargoResult<()> {
+    // Apparently build artifacts sprout up in Cargo's global cache for
+    // whatever reason, although it's unsure what tool is causing these issues
+    // at this time. For now we apply a heavy-hammer approach which is to
+    // delete Cargo's unpacked version of each crate to copy. After we do this
+    // we'll re-resolve and redownload again, which should trigger Cargo to
+    // re-extract those crates. Crates which are fresh aren't copied, so
+    // they're left alone rather than extracted again on every run.
+    //
+    // Note that errors are largely ignored here as this is a best-effort
+    // attempt. If anything fails here we basically just move on to the next
+    // crate to work with.
+    let mut cleared = HashSet::new();
+    for pkg in stale {
+        // Don't delete actual source code!
+        if pkg.package_id().source_id().is_path() {
+            continue;
+        }
+        if pkg.package_id().source_id().is_git() {
+            continue;
+        }
+        drop(fs::remove_dir_all(pkg.root()));
+        cleared.insert(pkg.package_id());
+    }
+    if cleared.is_empty() {
+        return Ok(());
+    }
+
+    for ws in workspaces {
+        let (packages, resolve) =
+            cargo::ops::resolve_ws(&ws).chain_err(|| "failed to load pkg lockfile")?;
+        packages.get_many(resolve.iter().filter(|id| cleared.contains(id)))?;
+    }
+    Ok(())
+}
+
+/// Creates the vendor directory, starting over if it was laid out with
+/// sources merged or not and now isn't.
+fn prepare_destination(options: &VendorOptions, path: &Path) -> CargoResult<()> {
+    let sources_file = path.join(SOURCES_FILE_NAME);
+    let is_multi_sources = sources_file.exists();
+    if is_multi_sources == options.merge_sources {
+        fs::remove_dir_all(path).ok();
+    }
+
+    fs::create_dir_all(&path).chain_err(|| format!("failed to create: `{}`", path.display()))?;
+
+    // A leftover index from vendoring as a local registry isn't a crate, so
+    // it wouldn't be cleaned up along with the `.crate` files.
+    let index = path.join("index");
+    if options.format == VendorFormat::Directory && !index.join("Cargo.toml").exists() {
+        fs::remove_dir_all(&index).ok();
+    }
+
+    if !is_multi_sources && !options.merge_sources {
+        let mut file = File::create(sources_file)?;
+        file.write_all(serde_json::json!([]).to_string().as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Warns about or rejects the git packages in `ids` which are only pinned to
+/// a branch, according to `mode`.
+fn check_branch_deps<I>(ids: I, mode: BranchDeps, config: &Config) -> CargoResult<()>
+where
+    I: IntoIterator<Item = PackageId>,
+{
+    if mode == BranchDeps::Allow {
+        return Ok(());
+    }
+    let mut unpinned = Vec::new();
+    for id in ids {
+        let source = id.source_id();
+        if let Some(GitReference::Branch(branch)) = source.git_reference() {
+            let commit = source.precise().unwrap_or("an unknown commit");
+            unpinned.push(format!(
+                "{} follows the branch `{}`, locked at {}",
+                id, branch, commit
+            ));
+        }
+    }
+    if unpinned.is_empty() {
+        return Ok(());
+    }
+    if mode == BranchDeps::Warn {
+        for message in unpinned {
+            config.shell().warn(format!(
+                "{}; pin it with `rev` or `tag` for reproducible builds",
+                message
+            ))?;
+        }
+        return Ok(());
+    }
+    let mut message = format!(
+        "{} git package{} only pinned to a branch, pin {} with `rev` or `tag`:",
+        unpinned.len(),
+        if unpinned.len() == 1 { " is" } else { "s are" },
+        if unpinned.len() == 1 { "it" } else { "them" }
+    );
+    for line in unpinned {
+        message.push_str("\n  ");
+        message.push_str(&line);
+    }
+    Err(failure::format_err!("{}", message))
+}
+
+fn collect_packages(
+    workspaces: &[Workspace],
+    options: &VendorOptions,
+    canonical_local_dst: &Path,
+    config: &Config,
+) -> CargoResult<Collected> {
+    let mut ids = BTreeMap::new();
+    let mut checksums = HashMap::new();
+    let mut dependencies = HashMap::new();
+    let mut lockfiles = HashMap::new();
+    let mut added_crates = Vec::new();
+    let mut skipped = BTreeMap::new();
+    let mut needed = HashSet::new();
+    let mut path_deps = HashSet::new();
+    let mut include_path_deps = Vec::new();
+    for pattern in options.include_path_deps.iter() {
+        let p = Pattern::new(pattern)
+            .chain_err(|| format!("invalid pattern `{}` of path dependencies", pattern))?;
+        include_path_deps.push(p);
+    }
+
+    let platforms = if options.platforms.is_empty() {
+        None
+    } else {
+        Some(Platforms::new(&options.platforms, config)?)
+    };
+
+    for ws in workspaces {
+        let main_pkg = ws.current().map(|x| x.name().as_str()).unwrap_or("");
+        let root = ws.root().canonicalize()?;
+        let (packages, resolve) =
+            cargo::ops::resolve_ws(&ws).chain_err(|| "failed to load pkg lockfile")?;
+        let event = Event::Resolve {
+            manifest_path: &ws.root().join("Cargo.toml"),
+            packages: resolve.iter().count(),
+        };
+        events::emit(options.message_format, config, event)?;
+
+        let mut unreachable = if platforms.is_some() || options.no_dev_deps {
+            filter::unreachable_packages(ws, &resolve, platforms.as_ref(), options.no_dev_deps)
+        } else {
+            BTreeMap::new()
+        };
+        if options.prune_optional_deps || !options.features.is_empty() {
+            for (id, reason) in filter::inactive_packages(ws, &resolve, &options.features)? {
+                unreachable.entry(id).or_insert(reason);
+            }
+        }
+        packages.get_many(resolve.iter())?;
+
+        for pkg in resolve.iter() {
+            dependencies
+                .entry(pkg)
+                .or_insert_with(BTreeSet::new)
+                .extend(resolve.deps(pkg).map(|(id, _)| id));
+            lockfiles
+                .entry(pkg)
+                .or_insert_with(BTreeSet::new)
+                .insert(ws.root().join("Cargo.lock"));
+            match unreachable.get(&pkg) {
+                Some(reason) => {
+                    skipped.entry(pkg).or_insert_with(|| reason.clone());
+                }
+                None => {
+                    needed.insert(pkg);
+                }
+            }
+            if pkg.source_id().is_path() {
+                let path = pkg.source_id().url().to_file_path().expect("path");
+                let canonical_path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+                let is_out_of_tree = !canonical_path.starts_with(&root)
+                    && !canonical_path.starts_with(canonical_local_dst);
+                if is_out_of_tree
+                    && include_path_deps
+                        .iter()
+                        .any(|p| p.matches(pkg.name().as_str()))
+                {
+                    path_deps.insert(pkg);
+                } else if !(options.vendor_main_crate && main_pkg == pkg.name().as_str()) {
+                    if canonical_path.starts_with(canonical_local_dst) {
+                        added_crates.push(canonical_path);
+                    }
+                    continue;
+                }
+            }
+            let package = packages
+                .get_one(pkg)
+                .chain_err(|| "failed to fetch package")?;
+            if !ids.contains_key(&pkg) {
+                let event = Event::Fetch {
+                    id: pkg,
+                    path: package.root(),
+                };
+                events::emit(options.message_format, config, event)?;
+            }
+            ids.insert(pkg, package.clone());
+
+            checksums.insert(pkg, resolve.checksums().get(&pkg).cloned().unwrap_or(None));
+        }
+    }
+
+    // A package may only be unused by some of the workspaces
+    let skipped = skipped
+        .into_iter()
+        .filter(|(id, _)| ids.contains_key(id) && !needed.contains(id))
+        .map(|(id, reason)| SkippedPackage { id, reason })
+        .collect::<Vec<_>>();
+    for pkg in skipped.iter() {
+        let event = Event::Skip {
+            id: pkg.id,
+            reason: &pkg.reason,
+        };
+        events::emit(options.message_format, config, event)?;
+    }
+    warn_missing_path_deps(&path_deps, &dependencies, config)?;
+
+    Ok(Collected {
+        ids,
+        checksums,
+        dependencies,
+        lockfiles,
+        added_crates,
+        skipped,
+        path_deps,
+    })
+}
+
+/// Warns about vendored path dependencies which depend on a path dependency
+/// that isn't vendored, as their copy can't be built without the original.
+fn warn_missing_path_deps(
+    path_deps: &HashSet<PackageId>,
+    dependencies: &HashMap<PackageId, BTreeSet<PackageId>>,
+    config: &Config,
+) -> CargoResult<()> {
+    let mut path_deps = path_deps.iter().collect::<Vec<_>>();
+    path_deps.sort();
+    for id in path_deps.iter() {
+        for dep in dependencies[id].iter() {
+            if dep.source_id().is_path() && !path_deps.contains(&dep) {
+                config.shell().warn(format!(
+                    "vendored path dependency {} depends on {}, which isn't \
+                     vendored",
+                    id, dep
+                ))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks that no version of a package is vendored from two sources, which
+/// would conflict in a merged directory, returning the sources of every
+/// version of each package.
+fn check_versions(
+    ids: &BTreeMap<PackageId, Package>,
+    merge_sources: bool,
+) -> CargoResult<HashMap<&'static str, BTreeMap<&Version, SourceId>>> {
+    // https://github.com/rust-lang/cargo/blob/373c5d8ce43691f90929a74b047d7eababd04379/src/cargo/sources/registry/mod.rs#L248
+
+    let mut versions = HashMap::new();
+    for id in ids.keys() {
+        let map = versions
+            .entry(id.name().as_str())
+            .or_insert_with(BTreeMap::default);
+
+        match map.get(&id.version()) {
+            Some(prev) if merge_sources => bail!(
+                "found duplicate version of package `{} v{}` \
+                 vendored from two sources:\n\
+                 \n\
+                 \tsource 1: {}\n\
+                 \tsource 2: {}",
+                id.name(),
+                id.version(),
+                prev,
+                id.source_id()
+            ),
+            _ => {}
+        }
+        map.insert(id.version(), id.source_id());
+    }
+    Ok(versions)
+}
+
+/// The directories crates are vendored into, decided before anything is
+/// written.
+struct Layout {
+    /// The directory of each source relative to the vendor directory, used
+    /// when sources aren't merged.
+    sources: BTreeMap<SourceId, String>,
+    /// The directory of each crate in the directory of its source.
+    crates: HashMap<PackageId, String>,
+}
+
+/// Names the directories of the crates in `ids` which are vendored, failing
+/// if two crates or two sources would share a directory.
+fn plan_layout(
+    ids: &BTreeMap<PackageId, Package>,
+    versions: &HashMap<&'static str, BTreeMap<&Version, SourceId>>,
+    options: &VendorOptions,
+    registry_names: &HashMap<SourceId, String>,
+) -> CargoResult<Layout> {
+    let dir_format = match &options.dir_format {
+        Some(template) => Some(DirFormat::parse(template)?),
+        None => None,
+    };
+    if let Some(dir_format) = &dir_format {
+        if dir_format.has_source_dir() && options.merge_sources {
+            bail!(
+                "the directory format `{}` places sources in separate \
+                 directories, which needs sources not to be merged",
+                options.dir_format.as_ref().unwrap()
+            );
+        }
+    }
+
+    let mut layout = Layout {
+        sources: BTreeMap::new(),
+        crates: HashMap::new(),
+    };
+    let mut source_owners = HashMap::new();
+    let mut crate_owners = HashMap::new();
+    for id in ids.keys() {
+        let max_version = *versions[id.name().as_str()].iter().rev().next().unwrap().0;
+        if id.version() != max_version && !options.explicit_version && options.disallow_duplicates {
+            bail!(
+                "found duplicate versions of package `{}` \
+                 at {} and {}, but this was disallowed via \
+                 --disallow-duplicates",
+                id.name(),
+                id.version(),
+                max_version
+            )
+        }
+        if !id.source_id().is_git() && options.only_git_deps {
+            continue;
+        }
+
+        let source_id = id.source_id();
+        if let Entry::Vacant(entry) = layout.sources.entry(source_id) {
+            let dir = match &dir_format {
+                Some(dir_format) => dir_format.source_dir(source_id, registry_names)?,
+                None => None,
+            };
+            let dir = dir.unwrap_or_else(|| source_id_to_dir_name(source_id, registry_names));
+            if !options.merge_sources {
+                if let Some(other) = source_owners.insert(dir.clone(), source_id) {
+                    bail!(
+                        "the sources `{}` and `{}` would both be vendored into `{}`, \
+                         `{{hash}}` in the directory format tells them apart",
+                        other,
+                        source_id,
+                        options.destination.join(&dir).display()
+                    );
+                }
+            }
+            entry.insert(dir);
+        }
+
+        let dst_name = match &dir_format {
+            Some(dir_format) => dir_format.crate_dir(*id, registry_names)?,
+            // Eg vendor/futures-0.1.13
+            None if options.explicit_version || id.version() != max_version => {
+                format!("{}-{}", id.name(), id.version())
+            }
+            // Eg vendor/futures
+            None => id.name().to_string(),
+        };
+        let mut dst = PathBuf::new();
+        if !options.merge_sources {
+            dst.push(&layout.sources[&source_id]);
+        }
+        dst.push(&dst_name);
+        if let Some(other) = crate_owners.insert(dst.clone(), *id) {
+            bail!(
+                "`{}` and `{}` would both be vendored into `{}`",
+                other,
+                id,
+                options.destination.join(&dst).display()
+            );
+        }
+        layout.crates.insert(*id, dst_name);
+    }
+    Ok(layout)
+}
+
+/// Writes a stub of `pkg` into the directory `dst` for a package which isn't
+/// needed, returning the checksums of its files.
+///
+/// Cargo's resolver still needs to find every package in the lock file in
+/// the vendor directory, even those it'll never build, so the stub contains
+/// the original manifest along with an empty file for each target.
+fn vendor_stub(
+    pkg: &Package,
+    dst: &Path,
+    options: &VendorOptions,
+    strip: &Strip,
+) -> CargoResult<BTreeMap<String, String>> {
+    let stripped_targets = strip.stripped_targets(pkg)?;
+    let _ = fs::remove_dir_all(&dst);
+    fs::create_dir_all(&dst)?;
+
+    let mut files = vec![PathBuf::from("Cargo.toml"), PathBuf::from(STUB_FILE_NAME)];
+    fs::copy(pkg.manifest_path(), dst.join("Cargo.toml"))?;
+    File::create(dst.join(STUB_FILE_NAME))?;
+    for target in pkg.targets() {
+        let relative = match target.src_path().path().map(|p| p.strip_prefix(pkg.root())) {
+            Some(Ok(relative)) => relative.to_path_buf(),
+            _ => continue,
+        };
+        if strip.is_stripped(&relative) {
+            continue;
+        }
+        let path = dst.join(&relative);
+        fs::create_dir_all(path.parent().unwrap())?;
+        File::create(&path)?;
+        files.push(relative);
+    }
+
+    let mut map = BTreeMap::new();
+    for file in files {
+        let key = file.to_str().unwrap().replace("\\", "/");
+        map.insert(key, sha256(&dst.join(&file))?);
+    }
+    rewrite_manifest(
+        pkg.root(),
+        dst,
+        &HashMap::new(),
+        options,
+        &stripped_targets,
+        &mut map,
+    )?;
+    Ok(map)
+}
+
+/// Applies the edits `options` calls for to the `Cargo.toml` of the crate
+/// vendored from `src` to `dst`, removes `stripped_targets` from it and points
+/// its dependencies on path dependencies at where they were `moved`, updating
+/// its checksum in `cksums` if it changed.
+fn rewrite_manifest(
+    src: &Path,
+    dst: &Path,
+    moved: &HashMap<PathBuf, PathBuf>,
+    options: &VendorOptions,
+    stripped_targets: &[StrippedTarget],
+    cksums: &mut BTreeMap<String, String>,
+) -> CargoResult<()> {
+    let path = dst.join("Cargo.toml");
+    let changed = manifest::edit_manifest(&path, |manifest| {
+        let mut changed = manifest::remove_targets(manifest, stripped_targets);
+        if !moved.is_empty() {
+            changed |= manifest::move_path_dependencies(manifest, src, dst, moved);
+        }
+        if options.no_dev_deps {
+            changed |= manifest::remove_dev_dependencies(manifest);
+        }
+        changed
+    })?;
+    if changed {
+        cksums.insert("Cargo.toml".to_string(), sha256(&path)?);
+    }
+    Ok(())
+}
+
+/// Lists the directories in the vendor directory which crates are placed in,
+/// either the vendor directory itself or one directory per source.
+fn source_paths(canonical_local_dst: &Path, merge_sources: bool) -> CargoResult<BTreeSet<PathBuf>> {
+    if merge_sources {
+        let mut set = BTreeSet::new();
+        set.insert(canonical_local_dst.to_path_buf());
+        Ok(set)
+    } else {
+        let sources_file = canonical_local_dst.join(SOURCES_FILE_NAME);
+        let file = File::open(&sources_file)?;
+        Ok(serde_json::from_reader::<_, BTreeSet<PathBuf>>(file)?
+            .into_iter()
+            .map(|p| canonical_local_dst.join(p))
+            .collect())
+    }
+}
+
+/// Lists the crates currently in the vendor directory.
+fn existing_crates(canonical_local_dst: &Path, merge_sources: bool) -> CargoResult<Vec<PathBuf>> {
+    Ok(source_paths(canonical_local_dst, merge_sources)?
+        .iter()
+        .flat_map(|path| {
+            path.read_dir()
+                .map(|iter| {
+                    iter.filter_map(|e| e.ok())
+                        .filter(|e| {
+                            let path = e.path();
+                            path.join("Cargo.toml").exists()
+                                || path.extension().and_then(|s| s.to_str()) == Some("crate")
+                        })
+                        .map(|e| e.path())
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default()
+        })
+        .collect())
+}
+
+/// A crate which is vendored as a directory, waiting for its files to be
+/// copied unless it's `fresh`.
+struct Pending<'a> {
+    pkg: &'a Package,
+    dst: PathBuf,
+    checksum: Option<&'a str>,
+    fingerprint: String,
+    patch_dir: Option<&'a Path>,
+    link_mode: LinkMode,
+    /// The files of a fresh crate which deduplication replaced with symbolic
+    /// links, with their checksums.
+    links: Vec<(PathBuf, String)>,
+    stripped_targets: Vec<StrippedTarget>,
+    fresh: bool,
+}
+
+/// Lists the sources of `pkg` to copy into the directory `dst`.
+fn copy_job<'a>(
+    pkg: &'a Package,
+    dst: &Path,
+    strip: &Strip,
+    link_mode: LinkMode,
+    config: &Config,
+) -> CargoResult<copy::CopyJob<'a>> {
+    let src = pkg
+        .manifest_path()
+        .parent()
+        .expect("manifest_path should point to a file");
+
+    let pathsource =
+        cargo::sources::path::PathSource::new(&src, pkg.package_id().source_id(), config);
+    let mut paths = pathsource.list_files(&pkg)?;
+    let is_git = pkg.package_id().source_id().is_git();
+    if is_git {
+        git::add_submodule_files(pkg, &mut paths)?;
+    }
+    paths.retain(|p| {
+        let relative = p.strip_prefix(&src).unwrap();
+        copy::is_vendored(relative) && !strip.is_stripped(relative)
+    });
+    if is_git {
+        git::check_lfs_pointers(pkg.package_id(), src, &paths)?;
+    }
+    Ok(copy::CopyJob {
+        id: pkg.package_id(),
+        src,
+        paths,
+        dst: dst.to_path_buf(),
+        link_mode,
+    })
+}
+
+/// Finishes vendoring the `pending` crate once its files, with the
+/// checksums `map`, were copied, returning the checksums of its final files.
+fn finish_directory(
+    pending: &Pending,
+    mut map: BTreeMap<String, String>,
+    moved: &HashMap<PathBuf, PathBuf>,
+    options: &VendorOptions,
+    config: &Config,
+) -> CargoResult<BTreeMap<String, String>> {
+    let id = pending.pkg.package_id();
+    let dst = &pending.dst;
+    let src = pending
+        .pkg
+        .manifest_path()
+        .parent()
+        .expect("manifest_path should point to a file");
+    let event = Event::Copy {
+        id,
+        src,
+        dst,
+        files: map.len(),
+    };
+    events::emit(options.message_format, config, event)?;
+    if let Some(patch_dir) = pending.patch_dir {
+        map = patch::apply(id, patch_dir, dst, config)?;
+    }
+    rewrite_manifest(
+        src,
+        dst,
+        moved,
+        options,
+        &pending.stripped_targets,
+        &mut map,
+    )?;
+    Ok(map)
+}
+
+/// The contents of the `.cargo-checksum.json` of a vendored crate.
+///
+/// Besides what Cargo needs, `source` and `fingerprint` record where the
+/// crate was vendored from and with which options.
+#[derive(Deserialize)]
+pub(crate) struct VendoredChecksums {
+    pub(crate) package: Option<String>,
+    #[serde(default)]
+    pub(crate) source: Option<String>,
+    #[serde(default)]
+    fingerprint: Option<String>,
+    pub(crate) files: BTreeMap<String, String>,
+}
+
+/// The parts of the manifest of a vendored crate which identify it.
+#[derive(Deserialize)]
+pub(crate) struct VendoredManifest {
+    // Old crates name the table `[project]`
+    #[serde(alias = "project")]
+    pub(crate) package: ManifestPackage,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ManifestPackage {
+    pub(crate) name: String,
+    pub(crate) version: Version,
+}
+
+/// Writes the `.cargo-checksum.json` file a directory source requires for
+/// the crate `id` vendored in `dst`.
+fn write_checksums(
+    id: PackageId,
+    checksum: Option<&str>,
+    dst: &Path,
+    fingerprint: &str,
+    files: &BTreeMap<String, String>,
+) -> CargoResult<()> {
+    let json = serde_json::json!({
+        "package": checksum,
+        "source": id.source_id().to_url().to_string(),
+        "fingerprint": fingerprint,
+        "files": files,
+    });
+    File::create(&dst.join(CHECKSUM_FILE_NAME))?.write_all(json.to_string().as_bytes())?;
+    Ok(())
+}
+
+/// Hashes everything besides the package itself which affects what's
+/// vendored for it, i.e. the options which change its files or how they're
+/// placed, and its patches.
+fn fingerprint(options: &VendorOptions, patch_dir: Option<&Path>) -> CargoResult<String> {
+    let mut sha = Sha256::new();
+    sha.update(env!("CARGO_PKG_VERSION").as_bytes());
+    for pattern in options.strip.iter() {
+        sha.update(b"\0strip ");
+        sha.update(pattern.as_bytes());
+    }
+    if options.no_dev_deps {
+        sha.update(b"\0no-dev-deps");
+    }
+    if patch_dir.is_none() && options.link_mode != LinkMode::Copy {
+        sha.update(format!("\0link {:?}", options.link_mode).as_bytes());
+    }
+    if let Some(dir) = patch_dir {
+        let mut patches = fs::read_dir(dir)
+            .chain_err(|| format!("failed to read `{}`", dir.display()))?
+            .map(|e| e.map(|e| e.path()))
+            .collect::<io::Result<Vec<_>>>()?;
+        patches.sort();
+        for patch in patches.iter().filter(|p| p.is_file()) {
+            sha.update(b"\0patch ");
+            sha.update(patch.file_name().unwrap().to_string_lossy().as_bytes());
+            sha.update(&fs::read(patch)?);
+        }
+    }
+    Ok(hex(&sha.finish()))
+}
+
+/// Returns whether `dst` already holds `id` as it would be vendored now,
+/// according to the checksum file recorded when it was vendored.
+///
+/// Only packages with a lock file checksum or a locked git revision qualify,
+/// as the contents of anything else, like a path dependency, may change at
+/// any time. A fresh crate's files which deduplication replaced with symbolic
+/// links are returned, as they're only fresh if what they link to is, see
+/// `check_links`.
+fn is_fresh(
+    id: PackageId,
+    checksum: Option<&str>,
+    dst: &Path,
+    stub: bool,
+    fingerprint: &str,
+) -> Option<Vec<(PathBuf, String)>> {
+    let source = id.source_id();
+    if checksum.is_none() && !(source.is_git() && source.precise().is_some()) {
+        return None;
+    }
+    if dst.join(STUB_FILE_NAME).exists() != stub {
+        return None;
+    }
+    let recorded = fs::read(dst.join(CHECKSUM_FILE_NAME))
+        .ok()
+        .and_then(|c| serde_json::from_slice::<VendoredChecksums>(&c).ok())?;
+    if recorded.package.as_ref().map(|s| s.as_str()) != checksum
+        || recorded.source != Some(source.to_url().to_string())
+        || recorded.fingerprint.as_ref().map(|s| s.as_str()) != Some(fingerprint)
+    {
+        return None;
+    }
+    let mut links = Vec::new();
+    for (file, cksum) in recorded.files {
+        let path = file
+            .split('/')
+            .fold(dst.to_path_buf(), |acc, component| acc.join(component));
+        let meta = fs::symlink_metadata(&path).ok()?;
+        // Stubs are never deduplicated
+        if meta.file_type().is_symlink() && !stub {
+            links.push((path, cksum));
+        } else if !meta.is_file() {
+            return None;
+        }
+    }
+    Some(links)
+}
+
+/// Marks fresh crates in `pending` whose symbolic links no longer point at
+/// what deduplication linked them to as stale.
+///
+/// A link is fresh if it points at a file with its recorded checksum in a
+/// fresh crate, as crates which aren't fresh are copied again or deleted,
+/// which would leave it dangling. As that may in turn make other
+/// crates stale, this repeats until nothing changes.
+fn check_links(pending: &mut [Pending]) {
+    let mut targets = Vec::new();
+    for p in pending.iter_mut().filter(|p| p.fresh) {
+        let resolved = p
+            .links
+            .iter()
+            .map(|(path, cksum)| link_target(path, cksum))
+            .collect::<Option<Vec<_>>>();
+        match resolved {
+            Some(resolved) => targets.push((p.dst.clone(), resolved)),
+            None => p.fresh = false,
+        }
+    }
+    loop {
+        let fresh = pending
+            .iter()
+            .filter(|p| p.fresh)
+            .map(|p| p.dst.as_path())
+            .collect::<HashSet<_>>();
+        let stale = targets
+            .iter()
+            .filter(|(dst, resolved)| {
+                fresh.contains(dst.as_path())
+                    && !resolved
+                        .iter()
+                        .all(|target| target.ancestors().any(|dir| fresh.contains(dir)))
+            })
+            .map(|(dst, _)| dst.clone())
+            .collect::<HashSet<_>>();
+        if stale.is_empty() {
+            return;
+        }
+        for p in pending.iter_mut().filter(|p| stale.contains(&p.dst)) {
+            p.fresh = false;
+        }
+    }
+}
+
+/// Returns the file the symbolic link `path` points at if its contents match
+/// `cksum`.
+fn link_target(path: &Path, cksum: &str) -> Option<PathBuf> {
+    let target = path.parent()?.join(fs::read_link(path).ok()?);
+    let target = target
+        .parent()?
+        .canonicalize()
+        .ok()?
+        .join(target.file_name()?);
+    if sha256(&target).ok()? != cksum {
+        return None;
+    }
+    Some(target)
+}
+
+/// Records the per-source subdirectories in use in the `.sources` file,
+/// removing the directories of sources which are now empty.
+fn update_sources_file(
+    canonical_local_dst: &Path,
+    sources: &BTreeMap<SourceId, String>,
+) -> CargoResult<()> {
+    let sources_file = canonical_local_dst.join(SOURCES_FILE_NAME);
+    let file = File::open(&sources_file)?;
+    let mut new_sources: BTreeSet<String> = sources.values().cloned().collect();
+    let old_sources: BTreeSet<String> = serde_json::from_reader::<_, BTreeSet<String>>(file)?
+        .difference(&new_sources)
+        .cloned()
+        .collect();
+    for dir_name in old_sources {
+        let path = canonical_local_dst.join(dir_name.clone());
+        if path.is_dir() {
+            if path.read_dir()?.next().is_none() {
+                fs::remove_dir(path)?;
+            } else {
+                new_sources.insert(dir_name.clone());
+            }
+        }
+    }
+    let file = File::create(sources_file)?;
+    serde_json::to_writer(file, &new_sources)?;
+    Ok(())
+}
+
+/// Generates the source replacement configuration needed to use the
+/// vendored `sources`.
+fn vendor_config(
+    sources: &BTreeMap<SourceId, String>,
+    registry_names: &HashMap<SourceId, String>,
+    options: &VendorOptions,
+    config: &Config,
+) -> VendorConfig {
+    let merge_sources = options.merge_sources;
+    // add our vendored source
+    let dir = config_dir(options, config);
+    let vendor_source = |path: PathBuf| match options.format {
+        VendorFormat::Directory => VendorSource::Directory { directory: path },
+        VendorFormat::LocalRegistry => VendorSource::LocalRegistry {
+            local_registry: path,
+        },
+    };
+    let mut config = BTreeMap::new();
+
+    if merge_sources {
+        config.insert(MERGED_SOURCE_NAME.to_string(), vendor_source(dir.clone()));
+    }
+
+    // replace original sources with vendor
+    for (&source_id, src_dir) in sources {
+        // Vendored path packages are used through their path rather than a
+        // source replacement
+        if source_id.is_path() {
+            continue;
+        }
+
+        let name = if source_id.is_default_registry() {
+            "crates-io".to_string()
+        } else if let Some(name) = registry_names.get(&source_id) {
+            name.clone()
+        } else {
+            source_id.url().to_string()
+        };
+
+        let replace_name = if !merge_sources {
+            format!("{}{}", SEPARATE_SOURCE_PREFIX, name)
+        } else {
+            MERGED_SOURCE_NAME.to_string()
+        };
+
+        if !merge_sources {
+            config.insert(replace_name.clone(), vendor_source(dir.join(src_dir)));
+        }
+
+        let source = if source_id.is_default_registry() {
+            VendorSource::Registry {
+                registry: None,
+                replace_with: replace_name,
+            }
+        } else if source_id.is_registry() {
+            VendorSource::Registry {
+                registry: Some(source_id.url().to_string()),
+                replace_with: replace_name,
+            }
+        } else if source_id.is_git() {
+            let mut branch = None;
+            let mut tag = None;
+            let mut rev = None;
+            if let Some(reference) = source_id.git_reference() {
+                match *reference {
+                    GitReference::Branch(ref b) => branch = Some(b.clone()),
+                    GitReference::Tag(ref t) => tag = Some(t.clone()),
+                    GitReference::Rev(ref r) => rev = Some(r.clone()),
+                }
+            }
+            VendorSource::Git {
+                git: source_id.url().to_string(),
+                branch,
+                tag,
+                rev,
+                replace_with: replace_name,
+            }
+        } else {
+            panic!("Invalid source ID: {}", source_id)
+        };
+        config.insert(name, source);
+    }
+
+    VendorConfig { source: config }
+}
+
+/// The path of the vendor directory to use in the configuration.
+fn config_dir(options: &VendorOptions, config: &Config) -> PathBuf {
+    let local_dst = options.destination.as_path();
+    if options.relative_path {
+        local_dst.to_path_buf()
+    } else {
+        config.cwd().join(local_dst)
+    }
+}
+
+/// Maps the sources of the alternative registries configured in
+/// `[registries]` to their names.
+fn registry_names(config: &Config) -> CargoResult<HashMap<SourceId, String>> {
+    let mut names = HashMap::new();
+    let registries = match config.get_table("registries")? {
+        Some(registries) => registries.val,
+        None => return Ok(names),
+    };
+    // The first name wins when two registries share an index
+    let mut registries = registries.keys().collect::<Vec<_>>();
+    registries.sort();
+    for name in registries {
+        // Registries may only have a token configured, without an index
+        let source_id = match SourceId::alt_registry(config, name) {
+            Ok(source_id) => source_id,
+            Err(_) => continue,
+        };
+        if !source_id.is_default_registry() {
+            names.entry(source_id).or_insert_with(|| name.clone());
+        }
+    }
+    Ok(names)
+}
+
+/// Names the directory of a source when sources aren't merged, which is
+/// `registry-<name>` for the alternative registries in `registry_names`.
+fn source_id_to_dir_name(src_id: SourceId, registry_names: &HashMap<SourceId, String>) -> String {
+    if let Some(name) = registry_names.get(&src_id) {
+        return format!("registry-{}", name);
+    }
+    format!("{}-{}", source_kind(src_id), source_hash(src_id))
+}
+
+/// A hash of the source `src_id` which stays the same across runs and
+/// platforms, as used in the names of directories.
+fn source_hash(src_id: SourceId) -> String {
+    let mut hasher = DefaultHasher::new();
+    src_id.stable_hash(Path::new(""), &mut hasher);
+    let src_hash = hasher.finish();
+    let mut bytes = [0; 8];
+    for (i, byte) in bytes.iter_mut().enumerate().take(7) {
+        *byte = (src_hash >> (i * 8)) as u8
+    }
+    hex(&bytes)
+}
+
+/// The kind of the source `src_id`, as used in the names of directories.
+fn source_kind(src_id: SourceId) -> &'static str {
+    if src_id.is_registry() {
+        "registry"
+    } else if src_id.is_git() {
+        "git"
+    } else if src_id.is_path() {
+        "path"
+    } else {
+        panic!()
+    }
+}
+
+/// The declared license of `pkg` as an SPDX expression, converting the
+/// deprecated `/` separator.
+fn license_expression(pkg: &Package) -> Option<String> {
+    let license = pkg.manifest().metadata().license.as_ref()?;
+    Some(
+        license
+            .split('/')
+            .map(|s| s.trim())
+            .collect::<Vec<_>>()
+            .join(" OR "),
+    )
+}
+
+/// The path of `to` relative to the directory `from`, both of which are
+/// absolute.
+fn relative_path(from: &Path, to: &Path) -> PathBuf {
+    let from = from.components().collect::<Vec<_>>();
+    let to = to.components().collect::<Vec<_>>();
+    let common = from
+        .iter()
+        .zip(to.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut path = PathBuf::new();
+    for _ in common..from.len() {
+        path.push(Component::ParentDir);
+    }
+    for component in to[common..].iter() {
+        path.push(component);
+    }
+    path
+}
+
+fn sha256(p: &Path) -> io::Result<String> {
+    let mut file = File::open(p)?;
+    let mut sha = Sha256::new();
+    let mut buf = [0; 2048];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        sha.update(&buf[..n]);
+    }
+    Ok(hex(&sha.finish()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len() * 2);
+    for &byte in bytes {
+        s.push(hex((byte >> 4) & 0xf));
+        s.push(hex(byte & 0xf));
+    }
+
+    return s;
+
+    fn hex(b: u8) -> char {
+        if b < 10 {
+            (b'0' + b) as char
+        } else {
+            (b'a' + b - 10) as char
+        }
+    }
+}
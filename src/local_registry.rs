@@ -0,0 +1,222 @@
+//! Support for vendoring into a Cargo local registry, that is a directory of
+//! `.crate` files alongside an `index` describing them.
+
+use crate::copy::{link_or_copy, LinkMode};
+use crate::events::{self, Event, MessageFormat};
+use crate::sha256;
+use cargo::core::dependency::Kind;
+use cargo::core::{Package, PackageId, SourceId};
+use cargo::util::{short_hash, CargoResult, CargoResultExt, Config};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// A line in a registry index file, see `RegistryPackage` in Cargo.
+#[derive(Serialize)]
+struct IndexPackage {
+    name: String,
+    vers: String,
+    deps: Vec<IndexDependency>,
+    features: BTreeMap<String, Vec<String>>,
+    cksum: String,
+    yanked: bool,
+    links: Option<String>,
+}
+
+#[derive(Serialize)]
+struct IndexDependency {
+    name: String,
+    req: String,
+    features: Vec<String>,
+    optional: bool,
+    default_features: bool,
+    target: Option<String>,
+    kind: &'static str,
+    registry: Option<String>,
+    package: Option<String>,
+}
+
+/// Name of the `.crate` file for `id` in a local registry.
+pub(crate) fn crate_file_name(id: PackageId) -> String {
+    format!("{}-{}.crate", id.name(), id.version())
+}
+
+/// Copies the `.crate` file Cargo downloaded for `id` into the registry at
+/// `dst_dir`, returning the path it was copied to and its checksum.
+///
+/// The file is only copied if it's not already present with the expected
+/// checksum.
+pub(crate) fn copy_crate_file(
+    id: PackageId,
+    checksum: Option<&str>,
+    dst_dir: &Path,
+    link_mode: LinkMode,
+    message_format: MessageFormat,
+    config: &Config,
+) -> CargoResult<(PathBuf, String)> {
+    let src = config
+        .registry_cache_path()
+        .join(registry_cache_name(id.source_id()))
+        .into_path_unlocked()
+        .join(crate_file_name(id));
+    let dst = dst_dir.join(crate_file_name(id));
+
+    if let Some(checksum) = checksum {
+        if dst.exists() && sha256(&dst)? == checksum {
+            events::emit(message_format, config, Event::Fresh { id, path: &dst })?;
+            return Ok((dst, checksum.to_string()));
+        }
+    }
+
+    // A stale file may be a read-only hard link
+    let _ = fs::remove_file(&dst);
+    let actual = link_or_copy(&src, &dst, link_mode)
+        .chain_err(|| format!("failed to copy `{}` to `{}`", src.display(), dst.display()))?;
+    let event = Event::Copy {
+        id,
+        src: &src,
+        dst: &dst,
+        files: 1,
+    };
+    events::emit(message_format, config, event)?;
+    if let Some(checksum) = checksum {
+        if actual != checksum {
+            failure::bail!(
+                "checksum of `{}` does not match the lock file, expected {} but found {}",
+                src.display(),
+                checksum,
+                actual
+            )
+        }
+    }
+    Ok((dst, actual))
+}
+
+/// Regenerates the `index` directory of the local registry at `root` so it
+/// describes exactly `packages`, given as pairs of package and the checksum
+/// of its `.crate` file. Dev-dependencies are left out of the index if
+/// `no_dev_deps` is set.
+pub(crate) fn write_index(
+    root: &Path,
+    packages: &[(&Package, String)],
+    no_dev_deps: bool,
+    config: &Config,
+) -> CargoResult<()> {
+    let index = root.join("index");
+    if index.exists() {
+        fs::remove_dir_all(&index)
+            .chain_err(|| format!("failed to remove: `{}`", index.display()))?;
+    }
+
+    let mut files = BTreeMap::new();
+    for (pkg, cksum) in packages {
+        let line = serde_json::to_string(&index_package(pkg, cksum, no_dev_deps, config)?)?;
+        files
+            .entry(index_file(&pkg.name()))
+            .or_insert_with(BTreeMap::new)
+            .insert(pkg.version().clone(), line);
+    }
+
+    for (file, lines) in files {
+        let path = file
+            .iter()
+            .fold(index.clone(), |acc, component| acc.join(component));
+        fs::create_dir_all(path.parent().unwrap())?;
+        let mut f =
+            File::create(&path).chain_err(|| format!("failed to create: `{}`", path.display()))?;
+        for line in lines.values() {
+            writeln!(f, "{}", line)?;
+        }
+    }
+    Ok(())
+}
+
+fn index_package(
+    pkg: &Package,
+    cksum: &str,
+    no_dev_deps: bool,
+    config: &Config,
+) -> CargoResult<IndexPackage> {
+    let registry_id = pkg.package_id().source_id();
+    let deps = pkg
+        .dependencies()
+        .iter()
+        .filter(|dep| !(no_dev_deps && dep.kind() == Kind::Development))
+        .map(|dep| {
+            // In the index `registry` is only present for dependencies on a
+            // registry other than the one the package itself is in.
+            let dep_registry_id = match dep.registry_id() {
+                Some(id) => id,
+                None => SourceId::crates_io(config)?,
+            };
+            let registry = if dep_registry_id != registry_id {
+                Some(dep_registry_id.url().to_string())
+            } else {
+                None
+            };
+            let (name, package) = match dep.explicit_name_in_toml() {
+                Some(name) => (name.to_string(), Some(dep.package_name().to_string())),
+                None => (dep.package_name().to_string(), None),
+            };
+            Ok(IndexDependency {
+                name,
+                req: dep.version_req().to_string(),
+                features: dep.features().iter().map(|s| s.to_string()).collect(),
+                optional: dep.is_optional(),
+                default_features: dep.uses_default_features(),
+                target: dep.platform().map(|s| s.to_string()),
+                kind: match dep.kind() {
+                    Kind::Normal => "normal",
+                    Kind::Build => "build",
+                    Kind::Development => "dev",
+                },
+                registry,
+                package,
+            })
+        })
+        .collect::<CargoResult<Vec<_>>>()?;
+
+    let summary = pkg.summary();
+    let features = summary
+        .features()
+        .iter()
+        .map(|(feat, values)| {
+            (
+                feat.to_string(),
+                values.iter().map(|fv| fv.to_string(summary)).collect(),
+            )
+        })
+        .collect();
+
+    Ok(IndexPackage {
+        name: pkg.name().to_string(),
+        vers: pkg.version().to_string(),
+        deps,
+        features,
+        cksum: cksum.to_string(),
+        yanked: false,
+        links: summary.links().map(|s| s.to_string()),
+    })
+}
+
+/// Path components of the index file for the crate `name`, following the
+/// layout of crates.io's index.
+fn index_file(name: &str) -> Vec<String> {
+    let name = name.to_lowercase();
+    match name.len() {
+        1 => vec!["1".to_string(), name],
+        2 => vec!["2".to_string(), name],
+        3 => vec!["3".to_string(), name[..1].to_string(), name],
+        _ => vec![name[..2].to_string(), name[2..4].to_string(), name],
+    }
+}
+
+/// Name of the directory in `~/.cargo/registry/cache` holding downloads of
+/// the registry `id`, see `short_name` in Cargo's registry source.
+fn registry_cache_name(id: SourceId) -> String {
+    let hash = short_hash(&id);
+    let ident = id.url().host_str().unwrap_or("").to_string();
+    format!("{}-{}", ident, hash)
+}
@@ -1,25 +1,27 @@
-use cargo::core::{enable_nightly_features, GitReference, SourceId, Workspace};
-use cargo::util::Sha256;
+use cargo::core::{enable_nightly_features, Workspace};
 use cargo::util::{CargoResult, CargoResultExt, Config};
+use cargo_vendor::{
+    BranchDeps, DedupMode, LinkMode, MessageFormat, NoticesFormat, SbomFormat, VendorFormat,
+    VendorOptions,
+};
 use docopt::Docopt;
-use failure::bail;
-use serde::{Deserialize, Serialize};
-use std::collections::hash_map::DefaultHasher;
-use std::collections::{BTreeMap, BTreeSet, HashMap};
-use std::fs::{self, File};
-use std::hash::Hasher;
-use std::io::{self, Read, Write};
-use std::path::{Path, PathBuf};
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
 
 #[derive(Deserialize)]
 struct Options {
     arg_path: Option<String>,
+    cmd_verify: bool,
+    cmd_audit: bool,
     flag_no_delete: Option<bool>,
     flag_version: bool,
     flag_sync: Option<Vec<String>>,
     flag_verbose: u32,
     flag_quiet: Option<bool>,
     flag_explicit_version: Option<bool>,
+    flag_dir_format: Option<String>,
     flag_color: Option<String>,
     flag_frozen: bool,
     flag_locked: bool,
@@ -28,35 +30,34 @@ struct Options {
     flag_only_git_deps: bool,
     flag_no_merge_sources: bool,
     flag_vendor_main_crate: bool,
+    flag_include_path_deps: Vec<String>,
+    flag_write_config: Option<String>,
+    flag_format: Option<String>,
+    flag_platform: Vec<String>,
+    flag_prune_optional_deps: bool,
+    flag_features: Vec<String>,
+    flag_no_dev_deps: bool,
+    flag_strip: Vec<String>,
+    flag_patches: Option<String>,
+    flag_policy: Option<String>,
+    flag_branch_deps: Option<String>,
+    flag_sbom_spdx: Option<String>,
+    flag_sbom_cyclonedx: Option<String>,
+    flag_notices: Option<String>,
+    flag_notices_format: Option<String>,
+    flag_diff: bool,
+    flag_diff_json: Option<String>,
+    flag_message_format: Option<String>,
+    flag_link_mode: Option<String>,
+    flag_dedup: bool,
+    flag_dedup_symlinks: bool,
+    flag_advisory_db: Option<String>,
+    flag_audit_ignore: Option<String>,
+    flag_audit_warn_only: bool,
 }
 
-#[derive(Serialize)]
-struct VendorConfig {
-    source: BTreeMap<String, VendorSource>,
-}
-
-#[derive(Serialize)]
-#[serde(rename_all = "lowercase", untagged)]
-enum VendorSource {
-    Directory {
-        directory: PathBuf,
-    },
-    Registry {
-        registry: Option<String>,
-        #[serde(rename = "replace-with")]
-        replace_with: String,
-    },
-    Git {
-        git: String,
-        branch: Option<String>,
-        tag: Option<String>,
-        rev: Option<String>,
-        #[serde(rename = "replace-with")]
-        replace_with: String,
-    },
-}
-
-const SOURCES_FILE_NAME: &str = ".sources";
+/// Default location for `--write-config` when no path is given.
+const DEFAULT_CONFIG_PATH: &str = ".cargo/config";
 
 fn main() {
     env_logger::init();
@@ -78,6 +79,8 @@ fn main() {
 Vendor all dependencies for a project locally
 
 Usage:
+    cargo vendor verify [options] [<path>]
+    cargo vendor audit [options] [<path>]
     cargo vendor [options] [<path>]
 
 Options:
@@ -87,6 +90,9 @@ Options:
     -v, --verbose ...        Use verbose output
     -q, --quiet              No output printed to stdout
     -x, --explicit-version   Always include version in subdir name
+    --dir-format TEMPLATE    Name crate directories after TEMPLATE, such as
+                             `{name}-{version}`, with `{source}`, `{rev}`,
+                             `{registry}` and `{hash}` also available
     --disallow-duplicates    Disallow two versions of one crate
     --no-delete              Don't delete older crates in the vendor directory
     --only-git-deps          Only vendor git dependencies, not crates.io dependencies
@@ -97,6 +103,47 @@ Options:
     --no-merge-sources       Keep sources separate
     --vendor-main-crate      Vendor the main crate as well (additionally to its
                              dependencies)
+    --include-path-deps=GLOB ...
+                             Vendor path dependencies outside the workspace
+                             whose name matches GLOB, all if GLOB is omitted
+    --write-config=PATH      Merge the source replacement configuration into
+                             PATH instead of printing it, PATH may be omitted
+                             to use .cargo/config
+    --format FORMAT          Vendor as a `directory` (default) or as a
+                             `local-registry` of .crate files
+    --link-mode MODE         Place files into the vendor directory with
+                             `copy` (default), `hardlink`, `reflink` or `auto`
+    --dedup                  Hard link identical files across vendored crates
+    --dedup-symlinks         Like --dedup, but with relative symbolic links
+    --platform TRIPLE ...    Only vendor crates needed to build for TRIPLE
+    --prune-optional-deps    Only vendor optional dependencies which are
+                             activated by default features
+    --features FEATURES ...  Features to activate in addition to the default
+                             ones, implies --prune-optional-deps
+    --no-dev-deps            Don't vendor crates only used as dev-dependencies
+    --strip GLOB ...         Leave files matching GLOB, such as `tests`, out of
+                             vendored crates
+    --sbom-spdx PATH         Write an SPDX software bill of materials to PATH
+    --sbom-cyclonedx PATH    Write a CycloneDX software bill of materials to
+                             PATH
+    --notices PATH           Write the license texts of all vendored crates to
+                             PATH
+    --notices-format FORMAT  Write --notices as `text` (default) or `markdown`
+    --message-format FMT     Report progress as `human` (default) or `json`
+                             messages
+    --diff                   Print a table of the crates which changed
+    --diff-json PATH         Write the crates and files which changed to PATH
+    --patches DIR            Apply the patches in DIR/<crate>-<version>/ to
+                             vendored crates
+    --policy FILE            Check vendored crates against the policy in FILE,
+                             `vendor-policy.toml` by default if it exists
+    --branch-deps MODE       `allow` (default), `warn` about or `deny` git
+                             dependencies only pinned to a branch
+    --advisory-db DIR        Check with `audit` against the advisory database
+                             checked out in DIR
+    --audit-ignore FILE      Don't fail `audit` on the advisories ignored in
+                             FILE
+    --audit-warn-only        Only report advisories found by `audit`
 
 This cargo subcommand will vendor all crates.io dependencies for a project into
 the specified directory at `<path>`. The `cargo vendor` command is intended to
@@ -107,11 +154,56 @@ from crates.io necessary to build the manifests specified.
 
 The `cargo vendor` command will also print out the configuration necessary
 to use the vendored sources, which when needed is then encoded into
-`.cargo/config`.
+`.cargo/config`. With `--write-config` the configuration is instead merged into
+that file directly, replacing only the `[source]` tables generated by a
+previous run of `cargo vendor`.
+
+The `cargo vendor verify` command checks every crate in an existing vendor
+directory against its `.cargo-checksum.json`, reporting missing, extra and
+modified files, and fails if any crate doesn't match.
+
+The `cargo vendor audit` command checks the versions of the crates in an
+existing vendor directory against a local checkout of the RustSec advisory
+database (https://github.com/RustSec/advisory-db) given with `--advisory-db`,
+and fails if any of them has a known vulnerability. Advisories can be ignored
+with an `--audit-ignore` file listing an `id` and a `reason` for each of them:
+
+    [[ignore]]
+    id = "RUSTSEC-2019-0001"
+    reason = "we never render untrusted SVG"
 "#;
 
+    // Docopt has no notion of options with an optional value, so expand a
+    // bare `--write-config` to one with the default path, and a bare
+    // `--include-path-deps` to one including every path dependency. A bare
+    // flag followed by another argument is ambiguous, as in `--write-config
+    // vendor` the argument may be the flag's value or the vendor directory,
+    // so the value has to be given as `--write-config=PATH` instead.
+    let args = std::env::args().collect::<Vec<_>>();
+    let mut argv = Vec::new();
+    for (i, arg) in args.iter().enumerate() {
+        let expanded = match arg.as_str() {
+            "--write-config" => format!("--write-config={}", DEFAULT_CONFIG_PATH),
+            "--include-path-deps" => "--include-path-deps=*".to_string(),
+            _ => {
+                argv.push(arg.clone());
+                continue;
+            }
+        };
+        if let Some(next) = args.get(i + 1).filter(|next| !next.starts_with('-')) {
+            let e = failure::format_err!(
+                "`{}` is followed by `{}`, which may be its value or the vendor \
+                 directory, use `{}=VALUE` to give it a value",
+                arg,
+                next,
+                arg
+            );
+            cargo::exit_with_error(e.into(), &mut *config.shell());
+        }
+        argv.push(expanded);
+    }
     let options = Docopt::new(usage)
-        .and_then(|d| d.deserialize())
+        .and_then(|d| d.argv(argv).deserialize())
         .unwrap_or_else(|e| e.exit());
     let result = real_main(options, &mut config);
     if let Err(e) = result {
@@ -141,21 +233,13 @@ fn real_main(options: Options, config: &mut Config) -> CargoResult<()> {
     )?;
 
     let default = "vendor".to_string();
-    let path = Path::new(options.arg_path.as_ref().unwrap_or(&default));
-
-    let sources_file = path.join(SOURCES_FILE_NAME);
-    let is_multi_sources = sources_file.exists();
-    if is_multi_sources && !options.flag_no_merge_sources
-        || !is_multi_sources && options.flag_no_merge_sources
-    {
-        fs::remove_dir_all(path).ok();
-    }
+    let path = options.arg_path.clone().unwrap_or(default);
 
-    fs::create_dir_all(&path).chain_err(|| format!("failed to create: `{}`", path.display()))?;
-
-    if !is_multi_sources && options.flag_no_merge_sources {
-        let mut file = File::create(sources_file)?;
-        file.write_all(serde_json::json!([]).to_string().as_bytes())?;
+    if options.cmd_verify {
+        return verify(&config.cwd().join(path), config);
+    }
+    if options.cmd_audit {
+        return audit(&config.cwd().join(path), &options, config);
     }
 
     let workspaces = match options.flag_sync {
@@ -177,432 +261,287 @@ fn real_main(options: Options, config: &mut Config) -> CargoResult<()> {
         }
     };
 
-    let vendor_config = sync(
-        &workspaces,
-        &path,
-        config,
-        options.flag_explicit_version.unwrap_or(false),
-        options.flag_no_delete.unwrap_or(false),
-        options.flag_disallow_duplicates,
-        options.flag_relative_path,
-        options.flag_only_git_deps,
-        !options.flag_no_merge_sources,
-        options.flag_vendor_main_crate,
-    )
-    .chain_err(|| format!("failed to sync"))?;
-
-    if !options.flag_quiet.unwrap_or(false) {
-        eprint!("To use vendored sources, add this to your .cargo/config for this project:\n\n");
-        print!("{}", &toml::to_string(&vendor_config).unwrap());
-    }
+    let format = match options.flag_format.as_ref().map(|s| s.as_str()) {
+        None | Some("directory") => VendorFormat::Directory,
+        Some("local-registry") => VendorFormat::LocalRegistry,
+        Some(other) => failure::bail!(
+            "unknown format `{}`, expected `directory` or `local-registry`",
+            other
+        ),
+    };
 
-    Ok(())
-}
+    let message_format = match options.flag_message_format.as_ref().map(|s| s.as_str()) {
+        None | Some("human") => MessageFormat::Human,
+        Some("json") => MessageFormat::Json,
+        Some(other) => failure::bail!(
+            "unknown message format `{}`, expected `human` or `json`",
+            other
+        ),
+    };
 
-fn sync(
-    workspaces: &[Workspace],
-    local_dst: &Path,
-    config: &Config,
-    explicit_version: bool,
-    no_delete: bool,
-    disallow_duplicates: bool,
-    use_relative_path: bool,
-    only_git_deps: bool,
-    merge_sources: bool,
-    vendor_main_crate: bool,
-) -> CargoResult<VendorConfig> {
-    let canonical_local_dst = local_dst.canonicalize().unwrap_or(local_dst.to_path_buf());
-    let mut ids = BTreeMap::new();
-    let mut added_crates = Vec::new();
-
-    // First up attempt to work around rust-lang/cargo#5956. Apparently build
-    // artifacts sprout up in Cargo's global cache for whatever reason, although
-    // it's unsure what tool is causing these issues at this time. For now we
-    // apply a heavy-hammer approach which is to delete Cargo's unpacked version
-    // of each crate to start off with. After we do this we'll re-resolve and
-    // redownload again, which should trigger Cargo to re-extract all the
-    // crates.
-    //
-    // Note that errors are largely ignored here as this is a best-effort
-    // attempt. If anything fails here we basically just move on to the next
-    // crate to work with.
-    for ws in workspaces {
-        let (packages, resolve) =
-            cargo::ops::resolve_ws(&ws).chain_err(|| "failed to load pkg lockfile")?;
-
-        packages.get_many(resolve.iter())?;
-
-        for pkg in resolve.iter() {
-            // Don't delete actual source code!
-            if pkg.source_id().is_path() {
-                continue;
-            }
-            if pkg.source_id().is_git() {
-                continue;
-            }
-            if let Ok(pkg) = packages.get_one(pkg) {
-                drop(fs::remove_dir_all(pkg.manifest_path().parent().unwrap()));
-            }
-        }
-    }
+    let link_mode = match options.flag_link_mode.as_ref().map(|s| s.as_str()) {
+        None | Some("copy") => LinkMode::Copy,
+        Some("hardlink") => LinkMode::Hardlink,
+        Some("reflink") => LinkMode::Reflink,
+        Some("auto") => LinkMode::Auto,
+        Some(other) => failure::bail!(
+            "unknown link mode `{}`, expected `copy`, `hardlink`, `reflink` or `auto`",
+            other
+        ),
+    };
 
-    let mut checksums = HashMap::new();
-
-    for ws in workspaces {
-        let main_pkg = ws.current().map(|x| x.name().as_str()).unwrap_or("");
-        let (packages, resolve) =
-            cargo::ops::resolve_ws(&ws).chain_err(|| "failed to load pkg lockfile")?;
-
-        packages.get_many(resolve.iter())?;
-
-        for pkg in resolve.iter() {
-            if pkg.source_id().is_path() {
-                let path = pkg.source_id().url().to_file_path().expect("path");
-                let canonical_path = path.canonicalize().unwrap_or(path.to_path_buf());
-                if !(vendor_main_crate && main_pkg == pkg.name().as_str()) {
-                    if canonical_path.starts_with(canonical_local_dst.as_path()) {
-                        added_crates.push(canonical_path);
-                    }
-                    continue;
-                }
-            }
-            ids.insert(
-                pkg.clone(),
-                packages
-                    .get_one(pkg)
-                    .chain_err(|| "failed to fetch package")?
-                    .clone(),
-            );
+    let branch_deps = match options.flag_branch_deps.as_ref().map(|s| s.as_str()) {
+        None | Some("allow") => BranchDeps::Allow,
+        Some("warn") => BranchDeps::Warn,
+        Some("deny") => BranchDeps::Deny,
+        Some(other) => failure::bail!(
+            "unknown branch dependency mode `{}`, expected `allow`, `warn` or `deny`",
+            other
+        ),
+    };
 
-            checksums.insert(pkg.clone(), resolve.checksums().get(&pkg).cloned());
-        }
+    let mut vendor_options = VendorOptions::new(path);
+    vendor_options
+        .explicit_version(options.flag_explicit_version.unwrap_or(false))
+        .no_delete(options.flag_no_delete.unwrap_or(false))
+        .disallow_duplicates(options.flag_disallow_duplicates)
+        .relative_path(options.flag_relative_path)
+        .only_git_deps(options.flag_only_git_deps)
+        .merge_sources(!options.flag_no_merge_sources)
+        .vendor_main_crate(options.flag_vendor_main_crate)
+        .format(format)
+        .link_mode(link_mode)
+        .branch_deps(branch_deps)
+        .diff(options.flag_diff || options.flag_diff_json.is_some());
+    if options.flag_dedup_symlinks {
+        vendor_options.dedup(DedupMode::Symlink);
+    } else if options.flag_dedup {
+        vendor_options.dedup(DedupMode::Hardlink);
     }
-
-    // https://github.com/rust-lang/cargo/blob/373c5d8ce43691f90929a74b047d7eababd04379/src/cargo/sources/registry/mod.rs#L248
-
-    let mut versions = HashMap::new();
-    for id in ids.keys() {
-        let map = versions.entry(id.name()).or_insert_with(BTreeMap::default);
-
-        match map.get(&id.version()) {
-            Some(prev) if merge_sources => bail!(
-                "found duplicate version of package `{} v{}` \
-                 vendored from two sources:\n\
-                 \n\
-                 \tsource 1: {}\n\
-                 \tsource 2: {}",
-                id.name(),
-                id.version(),
-                prev,
-                id.source_id()
-            ),
-            _ => {}
+    for triple in options.flag_platform.iter() {
+        vendor_options.platform(triple.as_str());
+    }
+    vendor_options
+        .prune_optional_deps(options.flag_prune_optional_deps)
+        .no_dev_deps(options.flag_no_dev_deps)
+        .message_format(message_format);
+    if let Some(template) = options.flag_dir_format {
+        vendor_options.dir_format(template);
+    }
+    for pattern in options.flag_strip.iter() {
+        vendor_options.strip(pattern.as_str());
+    }
+    if let Some(dir) = options.flag_patches {
+        vendor_options.patches(dir);
+    }
+    match options.flag_policy {
+        Some(file) => {
+            vendor_options.policy(file);
         }
-        map.insert(id.version(), id.source_id());
+        None if config.cwd().join("vendor-policy.toml").is_file() => {
+            vendor_options.policy("vendor-policy.toml");
+        }
+        None => {}
     }
-
-    let source_paths = if merge_sources {
-        let mut set = BTreeSet::new();
-        set.insert(canonical_local_dst.clone());
-        set
-    } else {
-        let sources_file = canonical_local_dst.join(SOURCES_FILE_NAME);
-        let file = File::open(&sources_file)?;
-        serde_json::from_reader::<_, BTreeSet<PathBuf>>(file)?
-            .into_iter()
-            .map(|p| canonical_local_dst.join(p))
-            .collect()
+    for feature in options.flag_features.iter() {
+        vendor_options.feature(feature.as_str());
+    }
+    for pattern in options.flag_include_path_deps.iter() {
+        vendor_options.include_path_deps(pattern.as_str());
+    }
+    let notices_format = match options.flag_notices_format.as_ref().map(|s| s.as_str()) {
+        None | Some("text") => NoticesFormat::Text,
+        Some("markdown") => NoticesFormat::Markdown,
+        Some(other) => failure::bail!(
+            "unknown notices format `{}`, expected `text` or `markdown`",
+            other
+        ),
     };
 
-    let existing_crates: Vec<PathBuf> = source_paths
-        .iter()
-        .flat_map(|path| {
-            path.read_dir()
-                .map(|iter| {
-                    iter.filter_map(|e| e.ok())
-                        .filter(|e| e.path().join("Cargo.toml").exists())
-                        .map(|e| e.path())
-                        .collect::<Vec<_>>()
-                })
-                .unwrap_or(Vec::new())
-        })
-        .collect();
-
-    let mut sources = BTreeSet::new();
-    for (id, pkg) in ids.iter() {
-        // Next up, copy it to the vendor directory
-        let src = pkg
-            .manifest_path()
-            .parent()
-            .expect("manifest_path should point to a file");
-        let max_version = *versions[&id.name()].iter().rev().next().unwrap().0;
-        let dir_has_version_suffix = explicit_version || id.version() != max_version;
-        let dst_name = if dir_has_version_suffix {
-            if !explicit_version && disallow_duplicates {
-                bail!(
-                    "found duplicate versions of package `{}` \
-                     at {} and {}, but this was disallowed via \
-                     --disallow-duplicates",
-                    pkg.name(),
-                    id.version(),
-                    max_version
-                )
-            }
-            // Eg vendor/futures-0.1.13
-            format!("{}-{}", id.name(), id.version())
-        } else {
-            // Eg vendor/futures
-            id.name().to_string()
-        };
-
-        if !id.source_id().is_git() && only_git_deps {
-            // Skip out if we only want to process git dependencies
-            continue;
+    let vendored = cargo_vendor::vendor(&workspaces, &vendor_options, config)?;
+
+    let sboms = [
+        (options.flag_sbom_spdx, SbomFormat::Spdx, "SPDX"),
+        (
+            options.flag_sbom_cyclonedx,
+            SbomFormat::CycloneDx,
+            "CycloneDX",
+        ),
+    ];
+    for (path, format, name) in sboms.iter() {
+        if let Some(path) = path {
+            let path = config.cwd().join(path);
+            config.shell().status(
+                "Writing",
+                format!("{} bill of materials to {}", name, path.display()),
+            )?;
+            cargo_vendor::write_sbom(&vendored, *format, &path)?;
         }
+    }
 
-        let source_dir = if merge_sources {
-            canonical_local_dst.clone()
+    let diff = vendored.diff.as_ref();
+    if let (true, Some(diff)) = (options.flag_diff, diff) {
+        if diff.is_empty() {
+            eprintln!("No vendored crates changed");
         } else {
-            canonical_local_dst.join(source_id_to_dir_name(id.source_id()))
-        };
-        if sources.insert(id.source_id()) && !merge_sources {
-            fs::create_dir_all(&source_dir)
-                .chain_err(|| format!("failed to create: `{}`", source_dir.display()))?;
+            eprint!("{}", diff);
         }
-        let dst = source_dir.join(&dst_name);
-        added_crates.push(dst.clone());
-
-        let cksum = dst.join(".cargo-checksum.json");
-        if dir_has_version_suffix && cksum.exists() {
-            // Always re-copy directory without version suffix in case the version changed
-            continue;
-        }
-
-        config.shell().status(
-            "Vendoring",
-            &format!("{} ({}) to {}", id, src.to_string_lossy(), dst.display()),
-        )?;
-
-        let _ = fs::remove_dir_all(&dst);
-        let pathsource = cargo::sources::path::PathSource::new(&src, id.source_id(), config);
-        let paths = pathsource.list_files(&pkg)?;
-        let mut map = BTreeMap::new();
-        cp_sources(&src, &paths, &dst, &mut map)
-            .chain_err(|| format!("failed to copy over vendored sources for: {}", id))?;
-
-        // Finally, emit the metadata about this package
-        let json = serde_json::json!({
-            "package": checksums.get(id),
-            "files": map,
-        });
-
-        File::create(&cksum)?.write_all(json.to_string().as_bytes())?;
     }
-
-    if !no_delete {
-        for path in existing_crates {
-            if !added_crates.contains(&path) {
-                fs::remove_dir_all(&path)?;
-            }
-        }
+    if let (Some(path), Some(diff)) = (options.flag_diff_json, diff) {
+        let path = config.cwd().join(path);
+        let json = serde_json::to_string_pretty(diff)?;
+        fs::write(&path, json).chain_err(|| format!("failed to write `{}`", path.display()))?;
     }
 
-    if !merge_sources {
-        let sources_file = canonical_local_dst.join(SOURCES_FILE_NAME);
-        let file = File::open(&sources_file)?;
-        let mut new_sources: BTreeSet<String> = sources
-            .iter()
-            .map(|src_id| source_id_to_dir_name(*src_id))
-            .collect();
-        let old_sources: BTreeSet<String> = serde_json::from_reader::<_, BTreeSet<String>>(file)?
-            .difference(&new_sources)
-            .map(|e| e.clone())
-            .collect();
-        for dir_name in old_sources {
-            let path = canonical_local_dst.join(dir_name.clone());
-            if path.is_dir() {
-                if path.read_dir()?.next().is_none() {
-                    fs::remove_dir(path)?;
-                } else {
-                    new_sources.insert(dir_name.clone());
-                }
-            }
+    if let Some(path) = options.flag_notices {
+        let path = config.cwd().join(path);
+        config.shell().status(
+            "Writing",
+            format!("third party notices to {}", path.display()),
+        )?;
+        let missing = cargo_vendor::write_notices(&vendored, notices_format, &path)?;
+        for id in missing {
+            config
+                .shell()
+                .warn(format!("no license text found for {}", id))?;
         }
-        let file = File::create(sources_file)?;
-        serde_json::to_writer(file, &new_sources)?;
     }
 
-    // add our vendored source
-    let dir = if use_relative_path {
-        local_dst.to_path_buf()
-    } else {
-        config.cwd().join(local_dst)
-    };
-    let mut config = BTreeMap::new();
-
-    let merged_source_name = "vendored-sources";
-    if merge_sources {
-        config.insert(
-            merged_source_name.to_string(),
-            VendorSource::Directory {
-                directory: dir.clone(),
-            },
-        );
+    let written_to = options.flag_write_config.map(|p| config.cwd().join(p));
+    if let Some(path) = &written_to {
+        config.shell().status(
+            "Writing",
+            format!("source configuration to {}", path.display()),
+        )?;
+        cargo_vendor::write_config(&vendored.config, path)?;
     }
-
-    // replace original sources with vendor
-    for source_id in sources {
-        let name = if source_id.is_default_registry() {
-            "crates-io".to_string()
-        } else {
-            source_id.url().to_string()
-        };
-
-        let replace_name = if !merge_sources {
-            format!("vendor+{}", name)
-        } else {
-            merged_source_name.to_string()
-        };
-
-        if !merge_sources {
-            let src_id_string = source_id_to_dir_name(source_id);
-            let src_dir = dir.join(src_id_string.clone());
-            config.insert(
-                replace_name.clone(),
-                VendorSource::Directory { directory: src_dir },
+    if message_format == MessageFormat::Json {
+        let message = serde_json::json!({
+            "reason": "config",
+            "config": vendored.config,
+            "written_to": written_to,
+            "path_dependencies": vendored.path_dependencies,
+        });
+        println!("{}", message);
+    } else if !options.flag_quiet.unwrap_or(false) {
+        if written_to.is_none() {
+            eprint!(
+                "To use vendored sources, add this to your .cargo/config for this project:\n\n"
             );
+            print!("{}", &toml::to_string(&vendored.config).unwrap());
         }
-
-        // if source id is a path and vendor_main_crate, skip the source replacement
-        if source_id.is_path() && vendor_main_crate {
-            continue;
-        }
-
-        let source = if source_id.is_default_registry() {
-            VendorSource::Registry {
-                registry: None,
-                replace_with: replace_name,
-            }
-        } else if source_id.is_git() {
-            let mut branch = None;
-            let mut tag = None;
-            let mut rev = None;
-            if let Some(reference) = source_id.git_reference() {
-                match *reference {
-                    GitReference::Branch(ref b) => branch = Some(b.clone()),
-                    GitReference::Tag(ref t) => tag = Some(t.clone()),
-                    GitReference::Rev(ref r) => rev = Some(r.clone()),
-                }
-            }
-            VendorSource::Git {
-                git: source_id.url().to_string(),
-                branch,
-                tag,
-                rev,
-                replace_with: replace_name,
+        if !vendored.path_dependencies.is_empty() {
+            eprint!(
+                "\nTo build without the original path dependencies, point them at \
+                 their vendored copies in Cargo.toml:\n\n"
+            );
+            for (src, dst) in vendored.path_dependencies.iter() {
+                eprintln!("{} -> {}", src.display(), dst.display());
             }
-        } else {
-            panic!("Invalid source ID: {}", source_id)
-        };
-        config.insert(name, source);
+        }
     }
 
-    Ok(VendorConfig { source: config })
+    Ok(())
 }
 
-fn cp_sources(
-    src: &Path,
-    paths: &Vec<PathBuf>,
-    dst: &Path,
-    cksums: &mut BTreeMap<String, String>,
-) -> CargoResult<()> {
-    for p in paths {
-        let relative = p.strip_prefix(&src).unwrap();
-
-        match relative.to_str() {
-            // Skip git config files as they're not relevant to builds most of
-            // the time and if we respect them (e.g.  in git) then it'll
-            // probably mess with the checksums when a vendor dir is checked
-            // into someone else's source control
-            Some(".gitattributes") | Some(".gitignore") | Some(".git") => continue,
-
-            // Temporary Cargo files
-            Some(".cargo-ok") => continue,
-
-            // Skip patch-style orig/rej files. Published crates on crates.io
-            // have `Cargo.toml.orig` which we don't want to use here and
-            // otherwise these are rarely used as part of the build process.
-            Some(filename) => {
-                if filename.ends_with(".orig") || filename.ends_with(".rej") {
-                    continue;
-                }
-            }
-            _ => (),
-        };
-
-        // Join pathname components individually to make sure that the joined
-        // path uses the correct directory separators everywhere, since
-        // `relative` may use Unix-style and `dst` may require Windows-style
-        // backslashes.
-        let dst = relative
+fn verify(path: &Path, config: &Config) -> CargoResult<()> {
+    let report = cargo_vendor::verify(path)?;
+    let mut failed = 0;
+    for krate in report.crates.iter().filter(|c| !c.is_ok()) {
+        failed += 1;
+        let name = krate.path.strip_prefix(path).unwrap_or(&krate.path);
+        let problems = krate
+            .missing
             .iter()
-            .fold(dst.to_owned(), |acc, component| acc.join(&component));
-
-        fs::create_dir_all(dst.parent().unwrap())?;
-
-        fs::copy(&p, &dst)
-            .chain_err(|| format!("failed to copy `{}` to `{}`", p.display(), dst.display()))?;
-        cksums.insert(relative.to_str().unwrap().replace("\\", "/"), sha256(&dst)?);
+            .map(|f| ("missing", f))
+            .chain(krate.extra.iter().map(|f| ("extra", f)))
+            .chain(krate.modified.iter().map(|f| ("modified", f)));
+        for (kind, file) in problems {
+            println!("{}: {} file `{}`", name.display(), kind, file);
+        }
     }
+    if failed > 0 {
+        failure::bail!(
+            "{} of {} vendored crates failed verification",
+            failed,
+            report.crates.len()
+        )
+    }
+    config.shell().status(
+        "Verified",
+        format!(
+            "{} vendored crates in {}",
+            report.crates.len(),
+            path.display()
+        ),
+    )?;
     Ok(())
 }
 
-fn source_id_to_dir_name(src_id: SourceId) -> String {
-    let src_type = if src_id.is_registry() {
-        "registry"
-    } else if src_id.is_git() {
-        "git"
-    } else {
-        panic!()
+fn audit(path: &Path, options: &Options, config: &Config) -> CargoResult<()> {
+    let db = match &options.flag_advisory_db {
+        Some(db) => config.cwd().join(db),
+        None => {
+            failure::bail!("`cargo vendor audit` needs the advisory database with --advisory-db")
+        }
     };
-    let mut hasher = DefaultHasher::new();
-    src_id.stable_hash(Path::new(""), &mut hasher);
-    let src_hash = hasher.finish();
-    let mut bytes = [0; 8];
-    for i in 0..7 {
-        bytes[i] = (src_hash >> i * 8) as u8
-    }
-    format!("{}-{}", src_type, hex(&bytes))
-}
-
-fn sha256(p: &Path) -> io::Result<String> {
-    let mut file = File::open(p)?;
-    let mut sha = Sha256::new();
-    let mut buf = [0; 2048];
-    loop {
-        let n = file.read(&mut buf)?;
-        if n == 0 {
-            break;
+    let ignores = match &options.flag_audit_ignore {
+        Some(file) => cargo_vendor::read_audit_ignores(&config.cwd().join(file))?,
+        None => Vec::new(),
+    };
+    let report = cargo_vendor::audit(path, &db, &ignores)?;
+
+    let mut vulnerabilities = 0;
+    let mut vulnerable = BTreeSet::new();
+    for finding in report.findings.iter() {
+        let message = format!(
+            "{} {} is affected by {}: {}",
+            finding.name, finding.version, finding.id, finding.title
+        );
+        if let Some(reason) = &finding.ignored {
+            config
+                .shell()
+                .status("Ignoring", format!("{} ({})", message, reason))?;
+        } else if let Some(kind) = &finding.informational {
+            config.shell().warn(format!("{} ({})", message, kind))?;
+        } else {
+            vulnerabilities += 1;
+            vulnerable.insert(&finding.path);
+            println!("{}", message);
+            println!("    path: {}", finding.path.display());
+            if let Some(url) = &finding.url {
+                println!("    url: {}", url);
+            }
+            if finding.patched.is_empty() {
+                println!("    patched: no fixed version");
+            } else {
+                println!("    patched: {}", finding.patched.join(" or "));
+            }
         }
-        sha.update(&buf[..n]);
     }
-    Ok(hex(&sha.finish()))
-}
-
-fn hex(bytes: &[u8]) -> String {
-    let mut s = String::with_capacity(bytes.len() * 2);
-    for &byte in bytes {
-        s.push(hex((byte >> 4) & 0xf));
-        s.push(hex((byte >> 0) & 0xf));
+    for id in report.unused_ignores.iter() {
+        config.shell().warn(format!(
+            "advisory {} is ignored but doesn't affect any vendored crate",
+            id
+        ))?;
     }
 
-    return s;
-
-    fn hex(b: u8) -> char {
-        if b < 10 {
-            (b'0' + b) as char
-        } else {
-            (b'a' + b - 10) as char
+    if !report.is_ok() {
+        let message = format!(
+            "{} vulnerabilities found in {} of {} vendored crates",
+            vulnerabilities,
+            vulnerable.len(),
+            report.crates
+        );
+        if !options.flag_audit_warn_only {
+            failure::bail!("{}", message)
         }
+        config.shell().warn(message)?;
+        return Ok(());
     }
+    config.shell().status(
+        "Audited",
+        format!("{} vendored crates in {}", report.crates, path.display()),
+    )?;
+    Ok(())
 }
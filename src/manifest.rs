@@ -0,0 +1,146 @@
+//! Rewriting of the `Cargo.toml` of vendored crates, e.g. to drop sections
+//! referring to packages which weren't vendored.
+
+use crate::relative_path;
+use crate::strip::StrippedTarget;
+use cargo::util::{CargoResult, CargoResultExt};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::value::{Table, Value};
+
+/// Keys of the tables listing dev-dependencies, Cargo accepts both spellings.
+const DEV_DEPENDENCY_KEYS: &[&str] = &["dev-dependencies", "dev_dependencies"];
+
+/// Keys of all tables listing dependencies.
+const DEPENDENCY_KEYS: &[&str] = &[
+    "dependencies",
+    "dev-dependencies",
+    "dev_dependencies",
+    "build-dependencies",
+    "build_dependencies",
+];
+
+/// Comment placed at the top of every rewritten manifest.
+const HEADER: &str = "\
+# This manifest was rewritten by `cargo vendor`, the original may contain
+# sections referring to files or packages which weren't vendored.
+
+";
+
+/// Applies `edit` to the manifest at `path`, returning whether it was
+/// changed. The file is only rewritten if `edit` returns `true`, so manifests
+/// which don't need editing are kept byte for byte.
+pub(crate) fn edit_manifest<F>(path: &Path, edit: F) -> CargoResult<bool>
+where
+    F: FnOnce(&mut Table) -> bool,
+{
+    let contents =
+        fs::read_to_string(path).chain_err(|| format!("failed to read `{}`", path.display()))?;
+    let mut manifest = toml::from_str::<Table>(&contents)
+        .chain_err(|| format!("failed to parse `{}`", path.display()))?;
+    if !edit(&mut manifest) {
+        return Ok(false);
+    }
+    let contents = format!("{}{}", HEADER, toml::to_string(&Value::Table(manifest))?);
+    // The manifest may be hard linked to Cargo's cache, which must not change
+    fs::remove_file(path).chain_err(|| format!("failed to remove `{}`", path.display()))?;
+    fs::write(path, contents).chain_err(|| format!("failed to write `{}`", path.display()))?;
+    Ok(true)
+}
+
+/// Removes all dev-dependencies, including platform specific ones, from
+/// `manifest`.
+pub(crate) fn remove_dev_dependencies(manifest: &mut Table) -> bool {
+    let mut changed = remove_keys(manifest, DEV_DEPENDENCY_KEYS);
+    if let Some(Value::Table(targets)) = manifest.get_mut("target") {
+        for (_, target) in targets.iter_mut() {
+            if let Value::Table(target) = target {
+                changed |= remove_keys(target, DEV_DEPENDENCY_KEYS);
+            }
+        }
+    }
+    changed
+}
+
+/// Removes the declarations of `targets` from `manifest`. Targets which are
+/// inferred rather than declared don't need to be removed.
+pub(crate) fn remove_targets(manifest: &mut Table, targets: &[StrippedTarget]) -> bool {
+    let mut changed = false;
+    for (key, name) in targets {
+        let now_empty = match manifest.get_mut(*key) {
+            Some(Value::Array(declared)) => {
+                let before = declared.len();
+                declared.retain(|t| t.get("name").and_then(|n| n.as_str()) != Some(name));
+                changed |= declared.len() != before;
+                declared.is_empty()
+            }
+            _ => false,
+        };
+        // An empty array would be emitted as a value after the tables
+        if now_empty {
+            manifest.remove(*key);
+        }
+    }
+    changed
+}
+
+/// Points the path dependencies of the manifest of a package copied from
+/// `src` to `dst` which were `moved`, given by their original directory, at
+/// their new directory.
+pub(crate) fn move_path_dependencies(
+    manifest: &mut Table,
+    src: &Path,
+    dst: &Path,
+    moved: &HashMap<PathBuf, PathBuf>,
+) -> bool {
+    let mut changed = move_in_tables(manifest, src, dst, moved);
+    if let Some(Value::Table(targets)) = manifest.get_mut("target") {
+        for (_, target) in targets.iter_mut() {
+            if let Value::Table(target) = target {
+                changed |= move_in_tables(target, src, dst, moved);
+            }
+        }
+    }
+    changed
+}
+
+fn move_in_tables(
+    table: &mut Table,
+    src: &Path,
+    dst: &Path,
+    moved: &HashMap<PathBuf, PathBuf>,
+) -> bool {
+    let mut changed = false;
+    for key in DEPENDENCY_KEYS {
+        let deps = match table.get_mut(*key) {
+            Some(Value::Table(deps)) => deps,
+            _ => continue,
+        };
+        for (_, dep) in deps.iter_mut() {
+            let path = match dep.get_mut("path") {
+                Some(path) => path,
+                None => continue,
+            };
+            let original = match path.as_str() {
+                Some(p) => src.join(p),
+                None => continue,
+            };
+            let original = original.canonicalize().unwrap_or(original);
+            if let Some(to) = moved.get(&original) {
+                let relative = relative_path(dst, to);
+                *path = Value::String(relative.to_string_lossy().replace('\\', "/"));
+                changed = true;
+            }
+        }
+    }
+    changed
+}
+
+fn remove_keys(table: &mut Table, keys: &[&str]) -> bool {
+    let mut changed = false;
+    for key in keys {
+        changed |= table.remove(*key).is_some();
+    }
+    changed
+}
@@ -0,0 +1,183 @@
+//! Generation of an attribution document holding the license texts of all
+//! vendored crates, e.g. to ship as `THIRD_PARTY_NOTICES`.
+
+use crate::{license_expression, Vendored, VendoredPackage};
+use cargo::core::PackageId;
+use cargo::util::{CargoResult, CargoResultExt};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The format of an attribution document written by [`write_notices`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NoticesFormat {
+    /// Markdown, with a section per license expression.
+    Markdown,
+    /// Plain text.
+    Text,
+}
+
+/// Prefixes of the names of files which hold license texts or notices,
+/// compared ignoring case.
+const LICENSE_FILE_PREFIXES: &[&str] = &["LICENSE", "LICENCE", "COPYING", "NOTICE", "UNLICENSE"];
+
+/// A crate's license texts, in the order they're written.
+struct Texts {
+    files: Vec<(String, String)>,
+}
+
+/// Writes an attribution document for `vendored` to `path`, returning the
+/// packages for which no license text was found.
+///
+/// Crates are grouped by the license expression in their manifest. The texts
+/// included for each crate are its `license-file`, if set, along with every
+/// file in its root directory whose name starts with `LICENSE`, `LICENCE`,
+/// `COPYING`, `NOTICE` or `UNLICENSE`.
+pub fn write_notices(
+    vendored: &Vendored,
+    format: NoticesFormat,
+    path: &Path,
+) -> CargoResult<Vec<PackageId>> {
+    let mut groups = BTreeMap::new();
+    let mut missing = Vec::new();
+    for pkg in vendored.packages.iter() {
+        let texts = license_texts(pkg)?;
+        if texts.files.is_empty() {
+            missing.push(pkg.id);
+        }
+        groups
+            .entry(license_group(pkg))
+            .or_insert_with(Vec::new)
+            .push((pkg, texts));
+    }
+
+    let contents = match format {
+        NoticesFormat::Markdown => markdown(vendored, &groups, &missing),
+        NoticesFormat::Text => text(vendored, &groups, &missing),
+    };
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .chain_err(|| format!("failed to create: `{}`", parent.display()))?;
+    }
+    fs::write(path, contents).chain_err(|| format!("failed to write `{}`", path.display()))?;
+    Ok(missing)
+}
+
+type Groups<'a> = BTreeMap<String, Vec<(&'a VendoredPackage, Texts)>>;
+
+fn markdown(vendored: &Vendored, groups: &Groups, missing: &[PackageId]) -> String {
+    let mut out = String::new();
+    writeln!(out, "# Third party notices\n").unwrap();
+    writeln!(
+        out,
+        "This project includes the following {} third party crates.\n",
+        vendored.packages.len()
+    )
+    .unwrap();
+    for (license, packages) in groups {
+        writeln!(out, "## {}\n", license).unwrap();
+        for (pkg, texts) in packages {
+            writeln!(out, "### {} {}\n", pkg.id.name(), pkg.id.version()).unwrap();
+            if texts.files.is_empty() {
+                writeln!(out, "No license text was found in this crate.\n").unwrap();
+            }
+            for (name, contents) in texts.files.iter() {
+                writeln!(out, "#### {}\n", name).unwrap();
+                writeln!(out, "```\n{}\n```\n", contents.trim_end()).unwrap();
+            }
+        }
+    }
+    if !missing.is_empty() {
+        writeln!(out, "## Crates without a license text\n").unwrap();
+        for id in missing {
+            writeln!(out, "* {} {}", id.name(), id.version()).unwrap();
+        }
+    }
+    out
+}
+
+fn text(vendored: &Vendored, groups: &Groups, missing: &[PackageId]) -> String {
+    let heavy = "=".repeat(78);
+    let light = "-".repeat(78);
+    let mut out = String::new();
+    writeln!(
+        out,
+        "THIRD PARTY NOTICES\n\nThis project includes the following {} third party crates.\n",
+        vendored.packages.len()
+    )
+    .unwrap();
+    for (license, packages) in groups {
+        writeln!(out, "{}\n{}\n{}\n", heavy, license, heavy).unwrap();
+        for (pkg, texts) in packages {
+            writeln!(out, "{} {}\n", pkg.id.name(), pkg.id.version()).unwrap();
+            if texts.files.is_empty() {
+                writeln!(out, "No license text was found in this crate.\n").unwrap();
+            }
+            for (name, contents) in texts.files.iter() {
+                writeln!(out, "{}\n{}\n{}\n", light, name, light).unwrap();
+                writeln!(out, "{}\n", contents.trim_end()).unwrap();
+            }
+        }
+    }
+    if !missing.is_empty() {
+        writeln!(out, "{}\nCrates without a license text\n{}\n", heavy, heavy).unwrap();
+        for id in missing {
+            writeln!(out, "{} {}", id.name(), id.version()).unwrap();
+        }
+    }
+    out
+}
+
+/// The heading `pkg` is listed under.
+fn license_group(pkg: &VendoredPackage) -> String {
+    match license_expression(&pkg.package) {
+        Some(expression) => expression,
+        None if pkg.package.manifest().metadata().license_file.is_some() => {
+            "Custom license".to_string()
+        }
+        None => "No license declared".to_string(),
+    }
+}
+
+/// Reads the license texts of `pkg`, from its vendored directory unless it
+/// was vendored as a `.crate` file.
+fn license_texts(pkg: &VendoredPackage) -> CargoResult<Texts> {
+    let root = if pkg.path.is_dir() {
+        pkg.path.clone()
+    } else {
+        pkg.package.root().to_path_buf()
+    };
+
+    let mut paths = Vec::<PathBuf>::new();
+    if let Some(file) = &pkg.package.manifest().metadata().license_file {
+        paths.push(root.join(file));
+    }
+    let mut found = Vec::new();
+    for entry in fs::read_dir(&root).chain_err(|| format!("failed to read `{}`", root.display()))? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap().to_string_lossy().to_uppercase();
+        if path.is_file() && LICENSE_FILE_PREFIXES.iter().any(|p| name.starts_with(p)) {
+            found.push(path);
+        }
+    }
+    found.sort();
+    found.retain(|p| !paths.contains(p));
+    paths.extend(found);
+
+    let mut files = Vec::new();
+    for path in paths {
+        // A `license-file` which was stripped or never existed is the same
+        // as having no license text.
+        if !path.is_file() {
+            continue;
+        }
+        let contents = fs::read(&path)?;
+        let name = path.strip_prefix(&root).unwrap_or(&path);
+        files.push((
+            name.display().to_string(),
+            String::from_utf8_lossy(&contents).into_owned(),
+        ));
+    }
+    Ok(Texts { files })
+}
@@ -0,0 +1,166 @@
+//! Application of local patches to vendored crates, for example to carry
+//! backports of fixes which haven't been published yet.
+//!
+//! Patches live in a directory with one subdirectory per crate, named
+//! `<name>-<version>`, holding `*.patch` files which are applied in order of
+//! their file names with `patch -p1` from the root of the vendored crate.
+
+use crate::sha256;
+use crate::verify::list_files;
+use cargo::core::PackageId;
+use cargo::util::{process, CargoResult, CargoResultExt, Config};
+use semver::Version;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The patch directories found for the packages being vendored.
+pub(crate) struct Patches {
+    dirs: BTreeMap<(String, Version), PathBuf>,
+}
+
+impl Patches {
+    /// Finds the patch directories in `dir`, if any.
+    ///
+    /// It's an error for a patch directory to be for a different version of
+    /// a crate than is being vendored, as that's usually the result of
+    /// updating the crate and forgetting about its patches.
+    pub(crate) fn new<'a, I>(dir: Option<&Path>, ids: I, config: &Config) -> CargoResult<Patches>
+    where
+        I: IntoIterator<Item = &'a PackageId>,
+    {
+        let mut dirs = BTreeMap::new();
+        let dir = match dir {
+            Some(dir) => dir,
+            None => return Ok(Patches { dirs }),
+        };
+        let entries =
+            fs::read_dir(dir).chain_err(|| format!("failed to read `{}`", dir.display()))?;
+        for entry in entries {
+            let path = entry?.path();
+            if !path.is_dir() {
+                continue;
+            }
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            match split_name_version(&name) {
+                Some(key) => {
+                    dirs.insert(key, path);
+                }
+                None => failure::bail!(
+                    "patch directory `{}` should be named `<crate>-<version>`",
+                    path.display()
+                ),
+            }
+        }
+
+        let mut vendored = BTreeMap::new();
+        for id in ids {
+            vendored
+                .entry(id.name().to_string())
+                .or_insert_with(Vec::new)
+                .push(id.version().clone());
+        }
+        for ((name, version), path) in dirs.iter() {
+            match vendored.get(name) {
+                Some(versions) if versions.contains(version) => {}
+                Some(versions) => failure::bail!(
+                    "the patches in `{}` are for {} v{}, but {} is vendored instead, \
+                     they need to be updated for the new version",
+                    path.display(),
+                    name,
+                    version,
+                    versions
+                        .iter()
+                        .map(|v| format!("v{}", v))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+                None => config.shell().warn(format!(
+                    "the patches in `{}` are for {} which isn't vendored",
+                    path.display(),
+                    name
+                ))?,
+            }
+        }
+        Ok(Patches { dirs })
+    }
+
+    /// Returns the directory of patches for `id`, if there is one.
+    pub(crate) fn get(&self, id: PackageId) -> Option<&Path> {
+        self.dirs
+            .get(&(id.name().to_string(), id.version().clone()))
+            .map(|p| p.as_path())
+    }
+}
+
+/// Splits a directory name like `foo-bar-1.0.0-beta` into the crate name and
+/// its version.
+fn split_name_version(name: &str) -> Option<(String, Version)> {
+    name.match_indices('-').find_map(|(i, _)| {
+        Version::parse(&name[i + 1..])
+            .ok()
+            .map(|v| (name[..i].to_string(), v))
+    })
+}
+
+/// Applies every patch in `dir` to the crate vendored in `dst`, returning the
+/// new checksums of all files in `dst`.
+///
+/// Each patch is first checked with a dry run so a patch which doesn't apply
+/// leaves no partial changes or reject files behind.
+pub(crate) fn apply(
+    id: PackageId,
+    dir: &Path,
+    dst: &Path,
+    config: &Config,
+) -> CargoResult<BTreeMap<String, String>> {
+    let mut patches = Vec::new();
+    for entry in fs::read_dir(dir).chain_err(|| format!("failed to read `{}`", dir.display()))? {
+        let path = entry?.path();
+        if path.extension().and_then(|s| s.to_str()) == Some("patch") {
+            patches.push(path);
+        }
+    }
+    patches.sort();
+
+    for patch in patches {
+        config
+            .shell()
+            .status("Patching", format!("{} with {}", id, patch.display()))?;
+        let mut cmd = process("patch");
+        cmd.arg("-p1")
+            .arg("--forward")
+            .arg("--batch")
+            .arg("-i")
+            .arg(&patch)
+            .cwd(dst);
+        let mut dry_run = cmd.clone();
+        dry_run.arg("--dry-run");
+        dry_run
+            .exec_with_output()
+            .and_then(|_| cmd.exec_with_output())
+            .chain_err(|| {
+                format!(
+                    "failed to apply patch `{}` to {}, it may need to be updated",
+                    patch.display(),
+                    id
+                )
+            })?;
+    }
+
+    // `patch` may leave backups behind, which are never vendored
+    let mut files = BTreeSet::new();
+    list_files(dst, "", &mut files)?;
+    let mut cksums = BTreeMap::new();
+    for file in files {
+        let path = file
+            .split('/')
+            .fold(dst.to_path_buf(), |acc, component| acc.join(component));
+        if file.ends_with(".orig") || file.ends_with(".rej") {
+            fs::remove_file(&path)?;
+            continue;
+        }
+        cksums.insert(file, sha256(&path)?);
+    }
+    Ok(cksums)
+}
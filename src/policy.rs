@@ -0,0 +1,345 @@
+//! Enforcement of a policy on which crates may be vendored, under which
+//! licenses and from which sources, usually kept in `vendor-policy.toml`.
+//!
+//! ```toml
+//! [crates]
+//! deny = ["openssl-sys", "time < 0.2"]
+//!
+//! [licenses]
+//! allow = ["MIT", "Apache-2.0", "BSD-3-Clause"]
+//!
+//! [sources]
+//! allow = ["crates-io", "https://git.example.com/"]
+//!
+//! [[exceptions]]
+//! name = "ring"
+//! licenses = ["ISC", "OpenSSL"]
+//! reason = "reviewed by legal"
+//! ```
+
+use crate::license_expression;
+use cargo::core::{Package, PackageId, SourceId};
+use cargo::util::{CargoResult, CargoResultExt, ToUrl};
+use semver::VersionReq;
+use serde::Deserialize;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The license of a package which doesn't declare a license expression, as
+/// in an SPDX document.
+const NO_LICENSE: &str = "NOASSERTION";
+
+/// The name of the default registry in the allowed sources.
+const CRATES_IO: &str = "crates-io";
+
+/// The rules read from a policy file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Policy {
+    #[serde(skip)]
+    path: PathBuf,
+    #[serde(default)]
+    crates: CrateRules,
+    #[serde(default)]
+    licenses: AllowList,
+    #[serde(default)]
+    sources: AllowList,
+    #[serde(default)]
+    exceptions: Vec<Exception>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct CrateRules {
+    /// A crate name, optionally followed by a version requirement.
+    #[serde(default)]
+    deny: Vec<String>,
+}
+
+/// A list of allowed values, where a missing list allows everything.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct AllowList {
+    allow: Option<Vec<String>>,
+}
+
+/// Additional licenses and sources allowed for some versions of a crate.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Exception {
+    name: String,
+    version: Option<String>,
+    #[serde(default)]
+    licenses: Vec<String>,
+    #[serde(default)]
+    sources: Vec<String>,
+    /// Why the exception is fine, which must be given.
+    #[serde(default)]
+    reason: String,
+}
+
+/// The name of a crate, and optionally a requirement its version matches.
+struct CrateSpec<'a> {
+    name: &'a str,
+    version: Option<&'a str>,
+}
+
+impl Policy {
+    /// Reads the policy file at `path`.
+    pub(crate) fn read(path: &Path) -> CargoResult<Policy> {
+        let contents = fs::read_to_string(path)
+            .chain_err(|| format!("failed to read `{}`", path.display()))?;
+        let mut policy: Policy = toml::from_str(&contents)
+            .chain_err(|| format!("failed to parse `{}`", path.display()))?;
+        policy.path = path.to_path_buf();
+        for exception in policy.exceptions.iter() {
+            if exception.reason.trim().is_empty() {
+                failure::bail!(
+                    "the exception for `{}` in `{}` has no reason, \
+                     every exception needs a justification",
+                    exception.name,
+                    path.display()
+                )
+            }
+        }
+        Ok(policy)
+    }
+
+    /// Checks every package in `packages` against the policy, failing with an
+    /// error which lists every violation.
+    pub(crate) fn check<'a, I>(&self, packages: I) -> CargoResult<()>
+    where
+        I: IntoIterator<Item = &'a Package>,
+    {
+        let mut violations = Vec::new();
+        let mut offenders = 0;
+        for pkg in packages {
+            let found = self
+                .violations(pkg)
+                .chain_err(|| format!("invalid vendor policy `{}`", self.path.display()))?;
+            if !found.is_empty() {
+                offenders += 1;
+            }
+            violations.extend(found.into_iter().map(|v| (pkg.package_id(), v)));
+        }
+        if violations.is_empty() {
+            return Ok(());
+        }
+
+        let mut message = format!(
+            "the vendor policy in `{}` is violated by {} package{}:",
+            self.path.display(),
+            offenders,
+            if offenders == 1 { "" } else { "s" }
+        );
+        for (id, violation) in violations {
+            write!(message, "\n  {}: {}", id, violation).unwrap();
+        }
+        Err(failure::format_err!("{}", message))
+    }
+
+    fn violations(&self, pkg: &Package) -> CargoResult<Vec<String>> {
+        let id = pkg.package_id();
+        let mut violations = Vec::new();
+        for deny in self.crates.deny.iter() {
+            if CrateSpec::parse(deny).matches(id)? {
+                violations.push(format!("denied by `{}`", deny));
+            }
+        }
+
+        let mut exceptions = Vec::new();
+        for exception in self.exceptions.iter() {
+            let spec = CrateSpec {
+                name: &exception.name,
+                version: exception.version.as_ref().map(|v| v.as_str()),
+            };
+            if spec.matches(id)? {
+                exceptions.push(exception);
+            }
+        }
+
+        if let Some(allowed) = &self.licenses.allow {
+            let expression = license_expression(pkg).unwrap_or_else(|| NO_LICENSE.to_string());
+            let is_allowed = |license: &str| {
+                allowed
+                    .iter()
+                    .chain(exceptions.iter().flat_map(|e| e.licenses.iter()))
+                    .any(|a| a == license)
+            };
+            match license_allowed(&expression, &is_allowed) {
+                Ok(true) => {}
+                Ok(false) if expression == NO_LICENSE => {
+                    violations.push("doesn't declare a license".to_string())
+                }
+                Ok(false) => violations.push(format!("license `{}` isn't allowed", expression)),
+                Err(e) => violations.push(format!(
+                    "license `{}` isn't a valid SPDX expression: {}",
+                    expression, e
+                )),
+            }
+        }
+
+        let source = id.source_id();
+        if let (Some(allowed), false) = (&self.sources.allow, source.is_path()) {
+            let mut is_allowed = false;
+            for allowed in allowed
+                .iter()
+                .chain(exceptions.iter().flat_map(|e| e.sources.iter()))
+            {
+                is_allowed |= source_allowed(allowed, source)?;
+            }
+            if !is_allowed {
+                violations.push(format!("source `{}` isn't allowed", source.url()));
+            }
+        }
+        Ok(violations)
+    }
+}
+
+/// Returns whether `source` is allowed by the entry `allowed` of a list of
+/// sources.
+///
+/// An entry other than `crates-io` is a URL, which allows sources with the
+/// same scheme, host and port, whose path is the entry's path or below it.
+fn source_allowed(allowed: &str, source: SourceId) -> CargoResult<bool> {
+    if allowed == CRATES_IO {
+        return Ok(source.is_default_registry());
+    }
+    let allowed = allowed.to_url()?;
+    let url = source.url();
+    if allowed.scheme() != url.scheme()
+        || allowed.host_str() != url.host_str()
+        || allowed.port_or_known_default() != url.port_or_known_default()
+    {
+        return Ok(false);
+    }
+    let prefix = allowed.path().trim_end_matches('/');
+    let path = url.path();
+    Ok(path == prefix || path.starts_with(&format!("{}/", prefix)))
+}
+
+impl<'a> CrateSpec<'a> {
+    /// Splits a spec like `time < 0.2` into the name and the requirement.
+    fn parse(spec: &'a str) -> CrateSpec<'a> {
+        let spec = spec.trim();
+        let end = spec
+            .find(|c: char| !c.is_alphanumeric() && c != '-' && c != '_')
+            .unwrap_or_else(|| spec.len());
+        let version = spec[end..].trim();
+        CrateSpec {
+            name: &spec[..end],
+            version: if version.is_empty() {
+                None
+            } else {
+                Some(version)
+            },
+        }
+    }
+
+    fn matches(&self, id: PackageId) -> CargoResult<bool> {
+        if id.name().as_str() != self.name {
+            return Ok(false);
+        }
+        let version = match self.version {
+            Some(version) => version,
+            None => return Ok(true),
+        };
+        let req = VersionReq::parse(version).chain_err(|| {
+            format!(
+                "invalid version requirement `{}` for `{}`",
+                version, self.name
+            )
+        })?;
+        Ok(req.matches(id.version()))
+    }
+}
+
+/// Returns whether the SPDX license `expression` can be satisfied with only
+/// licenses for which `allowed` returns true.
+///
+/// `AND` binds tighter than `OR`. A license with an exception, like
+/// `Apache-2.0 WITH LLVM-exception`, is allowed if either the whole of it or
+/// just the license is.
+fn license_allowed(expression: &str, allowed: &dyn Fn(&str) -> bool) -> Result<bool, String> {
+    let spaced = expression.replace('(', " ( ").replace(')', " ) ");
+    let tokens = spaced.split_whitespace().collect::<Vec<_>>();
+    let mut parser = LicenseParser {
+        tokens: &tokens,
+        pos: 0,
+        allowed,
+    };
+    let result = parser.or()?;
+    match parser.tokens.get(parser.pos) {
+        Some(token) => Err(format!("unexpected `{}`", token)),
+        None => Ok(result),
+    }
+}
+
+struct LicenseParser<'a> {
+    tokens: &'a [&'a str],
+    pos: usize,
+    allowed: &'a dyn Fn(&str) -> bool,
+}
+
+impl<'a> LicenseParser<'a> {
+    fn or(&mut self) -> Result<bool, String> {
+        let mut result = self.and()?;
+        while self.eat("OR") {
+            result |= self.and()?;
+        }
+        Ok(result)
+    }
+
+    fn and(&mut self) -> Result<bool, String> {
+        let mut result = self.license()?;
+        while self.eat("AND") {
+            result &= self.license()?;
+        }
+        Ok(result)
+    }
+
+    fn license(&mut self) -> Result<bool, String> {
+        let token = match self.tokens.get(self.pos) {
+            Some(token) => *token,
+            None => return Err("expected a license".to_string()),
+        };
+        self.pos += 1;
+        if token == "(" {
+            let result = self.or()?;
+            if !self.eat(")") {
+                return Err("unclosed `(`".to_string());
+            }
+            return Ok(result);
+        }
+        if token == ")" || is_operator(token) {
+            return Err(format!("expected a license but found `{}`", token));
+        }
+        if self.eat("WITH") {
+            let exception = match self.tokens.get(self.pos) {
+                Some(exception) if *exception != "(" && *exception != ")" => *exception,
+                _ => return Err(format!("expected an exception after `{} WITH`", token)),
+            };
+            self.pos += 1;
+            let full = format!("{} WITH {}", token, exception);
+            return Ok((self.allowed)(&full) || (self.allowed)(token));
+        }
+        Ok((self.allowed)(token))
+    }
+
+    fn eat(&mut self, expected: &str) -> bool {
+        match self.tokens.get(self.pos) {
+            Some(token) if token.eq_ignore_ascii_case(expected) => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+fn is_operator(token: &str) -> bool {
+    ["AND", "OR", "WITH"]
+        .iter()
+        .any(|op| token.eq_ignore_ascii_case(op))
+}
@@ -0,0 +1,275 @@
+//! Generation of a software bill of materials describing the vendored
+//! packages, either as an SPDX or as a CycloneDX document.
+
+use crate::{license_expression, Vendored};
+use cargo::core::PackageId;
+use cargo::util::{short_hash, CargoResult, CargoResultExt};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The format of a software bill of materials written by [`write_sbom`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SbomFormat {
+    /// An SPDX 2.2 document in its JSON serialization.
+    Spdx,
+    /// A CycloneDX 1.4 document in its JSON serialization.
+    CycloneDx,
+}
+
+/// Writes a software bill of materials for `vendored` to `path`.
+///
+/// Every vendored package is listed with its source, the license expression
+/// from its manifest and its checksum from the lock file, along with the
+/// dependencies between the vendored packages. The creation time recorded in
+/// the document is taken from `SOURCE_DATE_EPOCH` if it's set, so the output
+/// can be made reproducible.
+pub fn write_sbom(vendored: &Vendored, format: SbomFormat, path: &Path) -> CargoResult<()> {
+    let document = match format {
+        SbomFormat::Spdx => spdx(vendored),
+        SbomFormat::CycloneDx => cyclonedx(vendored),
+    };
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .chain_err(|| format!("failed to create: `{}`", parent.display()))?;
+    }
+    let contents = serde_json::to_string_pretty(&document)?;
+    fs::write(path, contents).chain_err(|| format!("failed to write `{}`", path.display()))?;
+    Ok(())
+}
+
+fn spdx(vendored: &Vendored) -> Value {
+    let refs = vendored
+        .packages
+        .iter()
+        .map(|p| (p.id, format!("SPDXRef-Package-{}", element_id(p.id))))
+        .collect::<HashMap<_, _>>();
+
+    let mut packages = Vec::new();
+    let mut relationships = Vec::new();
+    for pkg in vendored.packages.iter() {
+        let checksums = pkg
+            .checksum
+            .iter()
+            .map(|c| json!({ "algorithm": "SHA256", "checksumValue": c }))
+            .collect::<Vec<_>>();
+        packages.push(json!({
+            "SPDXID": refs[&pkg.id],
+            "name": pkg.id.name().as_str(),
+            "versionInfo": pkg.id.version().to_string(),
+            "downloadLocation": download_location(pkg.id),
+            "filesAnalyzed": false,
+            "licenseConcluded": "NOASSERTION",
+            "licenseDeclared": license_expression(&pkg.package)
+                .unwrap_or_else(|| "NOASSERTION".to_string()),
+            "copyrightText": "NOASSERTION",
+            "checksums": checksums,
+            "externalRefs": [{
+                "referenceCategory": "PACKAGE-MANAGER",
+                "referenceType": "purl",
+                "referenceLocator": purl(pkg.id),
+            }],
+        }));
+        relationships.push(json!({
+            "spdxElementId": "SPDXRef-DOCUMENT",
+            "relationshipType": "DESCRIBES",
+            "relatedSpdxElement": refs[&pkg.id],
+        }));
+        for dep in pkg.dependencies.iter() {
+            relationships.push(json!({
+                "spdxElementId": refs[&pkg.id],
+                "relationshipType": "DEPENDS_ON",
+                "relatedSpdxElement": refs[dep],
+            }));
+        }
+    }
+
+    // The namespace must be unique per document, so derive it from what's
+    // described rather than from the creation time.
+    let ids = vendored.packages.iter().map(|p| p.id).collect::<Vec<_>>();
+    let tool = format!(
+        "Tool: {}-{}",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION")
+    );
+    json!({
+        "spdxVersion": "SPDX-2.2",
+        "dataLicense": "CC0-1.0",
+        "SPDXID": "SPDXRef-DOCUMENT",
+        "name": "vendored-sources",
+        "documentNamespace": format!(
+            "https://spdx.org/spdxdocs/vendored-sources-{}",
+            short_hash(&ids)
+        ),
+        "creationInfo": {
+            "created": timestamp(),
+            "creators": [tool],
+        },
+        "packages": packages,
+        "relationships": relationships,
+    })
+}
+
+fn cyclonedx(vendored: &Vendored) -> Value {
+    let refs = vendored
+        .packages
+        .iter()
+        .map(|p| (p.id, element_id(p.id)))
+        .collect::<HashMap<_, _>>();
+
+    let mut components = Vec::new();
+    let mut dependencies = Vec::new();
+    for pkg in vendored.packages.iter() {
+        let mut component = json!({
+            "type": "library",
+            "bom-ref": refs[&pkg.id],
+            "name": pkg.id.name().as_str(),
+            "version": pkg.id.version().to_string(),
+            "purl": purl(pkg.id),
+        });
+        if let Some(license) = license_expression(&pkg.package) {
+            component["licenses"] = json!([{ "expression": license }]);
+        }
+        if let Some(checksum) = &pkg.checksum {
+            component["hashes"] = json!([{ "alg": "SHA-256", "content": checksum }]);
+        }
+        let source = pkg.id.source_id();
+        if source.is_git() {
+            let url = match source.precise() {
+                Some(rev) => format!("{}#{}", source.url(), rev),
+                None => source.url().to_string(),
+            };
+            component["externalReferences"] = json!([{ "type": "vcs", "url": url }]);
+        } else if source.is_default_registry() {
+            component["externalReferences"] =
+                json!([{ "type": "distribution", "url": download_location(pkg.id) }]);
+        }
+        components.push(component);
+
+        let depends_on = pkg
+            .dependencies
+            .iter()
+            .map(|d| &refs[d])
+            .collect::<Vec<_>>();
+        dependencies.push(json!({ "ref": refs[&pkg.id], "dependsOn": depends_on }));
+    }
+
+    json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": "1.4",
+        "version": 1,
+        "metadata": {
+            "timestamp": timestamp(),
+            "tools": [{
+                "name": env!("CARGO_PKG_NAME"),
+                "version": env!("CARGO_PKG_VERSION"),
+            }],
+        },
+        "components": components,
+        "dependencies": dependencies,
+    })
+}
+
+/// A unique identifier for `id` which is valid in both formats, i.e. only
+/// contains letters, digits, `.` and `-`.
+fn element_id(id: PackageId) -> String {
+    let raw = format!("{}-{}-{}", id.name(), id.version(), short_hash(&id));
+    raw.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+/// Where the package can be downloaded from, in the syntax used by SPDX.
+fn download_location(id: PackageId) -> String {
+    let source = id.source_id();
+    if source.is_default_registry() {
+        format!(
+            "https://crates.io/api/v1/crates/{}/{}/download",
+            id.name(),
+            id.version()
+        )
+    } else if source.is_git() {
+        match source.precise() {
+            Some(rev) => format!("git+{}@{}", source.url(), rev),
+            None => format!("git+{}", source.url()),
+        }
+    } else {
+        "NOASSERTION".to_string()
+    }
+}
+
+/// The package URL of `id`, see https://github.com/package-url/purl-spec.
+fn purl(id: PackageId) -> String {
+    let base = format!("pkg:cargo/{}@{}", id.name(), id.version());
+    let source = id.source_id();
+    if source.is_default_registry() || source.is_path() {
+        base
+    } else if source.is_git() {
+        let vcs = match source.precise() {
+            Some(rev) => format!("git+{}@{}", source.url(), rev),
+            None => format!("git+{}", source.url()),
+        };
+        format!("{}?vcs_url={}", base, percent_encode(&vcs))
+    } else {
+        let url = source.url().to_string();
+        format!("{}?repository_url={}", base, percent_encode(&url))
+    }
+}
+
+fn percent_encode(s: &str) -> String {
+    let mut encoded = String::new();
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(b as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+/// The current time, or `SOURCE_DATE_EPOCH` if set, in the RFC 3339 format
+/// both formats use.
+fn timestamp() -> String {
+    let secs = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0)
+        });
+    let (days, rem) = (secs / 86400, secs % 86400);
+
+    // Converts days since the epoch to a date in the proleptic Gregorian
+    // calendar, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
@@ -0,0 +1,89 @@
+//! Removal of files which aren't needed to build a crate, such as its tests,
+//! benchmarks and examples, from the vendored copy.
+
+use cargo::core::{Package, Target};
+use cargo::util::{CargoResult, CargoResultExt};
+use glob::{MatchOptions, Pattern};
+use std::path::Path;
+
+/// A set of glob patterns of files to leave out of vendored crates.
+pub(crate) struct Strip {
+    patterns: Vec<Pattern>,
+}
+
+/// A target in the manifest which points at stripped files, given as the
+/// array of tables it's declared in, e.g. `test`, and its name.
+pub(crate) type StrippedTarget = (&'static str, String);
+
+impl Strip {
+    /// Compiles `patterns`, which are matched against paths relative to the
+    /// root of each crate with `/` as the separator.
+    pub(crate) fn new(patterns: &[String]) -> CargoResult<Strip> {
+        let mut compiled = Vec::new();
+        for pattern in patterns {
+            let p = Pattern::new(pattern.trim_end_matches('/'))
+                .chain_err(|| format!("invalid pattern `{}` to strip", pattern))?;
+            compiled.push(p);
+        }
+        Ok(Strip { patterns: compiled })
+    }
+
+    /// Returns whether `relative` should be left out, either because it
+    /// matches one of the patterns itself or because one of its parent
+    /// directories does.
+    pub(crate) fn is_stripped(&self, relative: &Path) -> bool {
+        let options = MatchOptions {
+            require_literal_separator: true,
+            ..MatchOptions::new()
+        };
+        relative.ancestors().any(|path| {
+            !path.as_os_str().is_empty()
+                && self
+                    .patterns
+                    .iter()
+                    .any(|p| p.matches_path_with(path, options))
+        })
+    }
+
+    /// Lists the test, bench and example targets of `pkg` whose source was
+    /// stripped, so they can be removed from its manifest.
+    ///
+    /// Stripping the source of any other target would leave a crate which
+    /// can't be built, so that's an error.
+    pub(crate) fn stripped_targets(&self, pkg: &Package) -> CargoResult<Vec<StrippedTarget>> {
+        let mut stripped = Vec::new();
+        for target in pkg.targets() {
+            let relative = match target.src_path().path().map(|p| p.strip_prefix(pkg.root())) {
+                Some(Ok(relative)) => relative,
+                _ => continue,
+            };
+            if !self.is_stripped(relative) {
+                continue;
+            }
+            match manifest_key(target) {
+                Some(key) => stripped.push((key, target.name().to_string())),
+                None => failure::bail!(
+                    "stripping `{}` would remove the source of target `{}` of package `{}`",
+                    relative.display(),
+                    target.name(),
+                    pkg.package_id()
+                ),
+            }
+        }
+        Ok(stripped)
+    }
+}
+
+/// Name of the array of tables `target` is declared in, for the kinds of
+/// target which may be stripped.
+fn manifest_key(target: &Target) -> Option<&'static str> {
+    if target.is_test() {
+        Some("test")
+    } else if target.is_bench() {
+        Some("bench")
+    } else if target.is_example() {
+        Some("example")
+    } else {
+        None
+    }
+}
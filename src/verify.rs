@@ -0,0 +1,135 @@
+//! Verification of a vendor directory against the `.cargo-checksum.json`
+//! files written when it was created.
+
+use crate::info::VENDOR_INFO_FILE_NAME;
+use crate::{sha256, source_paths, VendoredChecksums, CHECKSUM_FILE_NAME, SOURCES_FILE_NAME};
+use cargo::util::{CargoResult, CargoResultExt};
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+/// The result of verifying a vendor directory with [`verify`].
+pub struct VerifyReport {
+    /// One entry per vendored crate, sorted by path.
+    pub crates: Vec<CrateVerification>,
+}
+
+/// Differences between a vendored crate and its `.cargo-checksum.json`.
+///
+/// All file names are relative to the crate's directory and use `/` as the
+/// separator, just like in the checksum file.
+pub struct CrateVerification {
+    /// Directory of the vendored crate.
+    pub path: PathBuf,
+    /// Files listed in the checksum file which no longer exist. This
+    /// includes the checksum file itself if it's missing.
+    pub missing: Vec<String>,
+    /// Files which aren't listed in the checksum file.
+    pub extra: Vec<String>,
+    /// Files whose contents no longer match their checksum.
+    pub modified: Vec<String>,
+}
+
+impl VerifyReport {
+    /// Returns whether every crate matched its checksums.
+    pub fn is_ok(&self) -> bool {
+        self.crates.iter().all(|c| c.is_ok())
+    }
+}
+
+impl CrateVerification {
+    /// Returns whether the crate matched its checksums.
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty() && self.modified.is_empty()
+    }
+}
+
+/// Recomputes the checksums of every file in the vendor directory at `path`.
+///
+/// Both the merged layout and the per-source layout of `--no-merge-sources`
+/// are supported.
+pub fn verify(path: &Path) -> CargoResult<VerifyReport> {
+    if !path.is_dir() {
+        failure::bail!("vendor directory `{}` does not exist", path.display())
+    }
+    let merge_sources = !path.join(SOURCES_FILE_NAME).exists();
+
+    // Anything that looks like it's meant to be a crate is checked, so a
+    // crate which lost its `Cargo.toml` is still reported.
+    let mut dirs = Vec::new();
+    for source in source_paths(path, merge_sources)? {
+        for entry in fs::read_dir(&source)? {
+            let dir = entry?.path();
+            if dir.join("Cargo.toml").exists() || dir.join(CHECKSUM_FILE_NAME).exists() {
+                dirs.push(dir);
+            }
+        }
+    }
+    dirs.sort();
+
+    let mut crates = Vec::new();
+    for dir in dirs {
+        let krate =
+            verify_crate(&dir).chain_err(|| format!("failed to verify `{}`", dir.display()))?;
+        crates.push(krate);
+    }
+    Ok(VerifyReport { crates })
+}
+
+fn verify_crate(dir: &Path) -> CargoResult<CrateVerification> {
+    let mut result = CrateVerification {
+        path: dir.to_path_buf(),
+        missing: Vec::new(),
+        extra: Vec::new(),
+        modified: Vec::new(),
+    };
+
+    let mut actual = BTreeSet::new();
+    list_files(dir, "", &mut actual)?;
+    // The provenance of the crate is deliberately not checksummed
+    actual.remove(VENDOR_INFO_FILE_NAME);
+
+    let cksum = dir.join(CHECKSUM_FILE_NAME);
+    if !actual.remove(CHECKSUM_FILE_NAME) {
+        result.missing.push(CHECKSUM_FILE_NAME.to_string());
+        result.extra.extend(actual);
+        return Ok(result);
+    }
+    let checksums: VendoredChecksums = serde_json::from_reader(File::open(&cksum)?)
+        .chain_err(|| format!("failed to parse `{}`", cksum.display()))?;
+
+    for (file, expected) in checksums.files.iter() {
+        if !actual.remove(file) {
+            result.missing.push(file.clone());
+            continue;
+        }
+        let path = file
+            .split('/')
+            .fold(dir.to_path_buf(), |acc, component| acc.join(component));
+        if sha256(&path)? != *expected {
+            result.modified.push(file.clone());
+        }
+    }
+    result.extra.extend(actual);
+    Ok(result)
+}
+
+/// Recursively lists all files in `dir`, relative to the crate root and with
+/// `/` as the separator.
+pub(crate) fn list_files(
+    dir: &Path,
+    prefix: &str,
+    files: &mut BTreeSet<String>,
+) -> CargoResult<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let relative = format!("{}{}", prefix, name);
+        if entry.path().is_dir() {
+            list_files(&entry.path(), &format!("{}/", relative), files)?;
+        } else {
+            files.insert(relative);
+        }
+    }
+    Ok(())
+}
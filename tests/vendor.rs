@@ -10,6 +10,7 @@ use std::sync::{Mutex, MutexGuard};
 
 use once_cell::sync::OnceCell;
 
+#[allow(clippy::needless_return)]
 fn vendor(dir: &Path) -> Command {
     let mut me = env::current_exe().unwrap();
     me.pop();
@@ -575,6 +576,7 @@ fn switch_merged_source() {
 }
 
 #[test]
+#[allow(unused_variables)]
 fn main_crate_vendored() {
     let (dir, _lock) = dir();
 
@@ -594,6 +596,7 @@ fn main_crate_vendored() {
 }
 
 #[test]
+#[allow(unused_variables)]
 fn path_dependency_ignored_when_main_crate_vendored() {
     let (dir, _lock) = dir();
 
@@ -621,3 +624,1057 @@ fn path_dependency_ignored_when_main_crate_vendored() {
     assert!(dir.join("vendor/foo").is_dir());
     assert!(!dir.join("vendor/subcrate").is_dir());
 }
+
+#[test]
+fn library_vendor() {
+    use cargo::core::{Shell, Workspace};
+    use cargo::util::Config;
+
+    let (dir, _lock) = dir();
+
+    file(&dir, "Cargo.toml", r#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+
+        [dependencies]
+        bitflags = "=0.7.0"
+    "#);
+    file(&dir, "src/lib.rs", "");
+
+    let new_config = || {
+        Config::new(Shell::new(), dir.clone(), cargo::util::homedir(&dir).unwrap())
+    };
+    let mut values = new_config().values().unwrap().clone();
+    values.remove("source");
+    let config = new_config();
+    config.set_values(values).unwrap();
+    let ws = Workspace::new(&dir.join("Cargo.toml"), &config).unwrap();
+
+    // Relative to the directory of the `Config`, not of the process
+    let mut options = cargo_vendor::VendorOptions::new("vendor");
+    options.explicit_version(true);
+    let vendored = cargo_vendor::vendor(&[ws], &options, &config).unwrap();
+
+    assert_eq!(vendored.packages.len(), 1);
+    assert_eq!(vendored.packages[0].id.name().as_str(), "bitflags");
+    assert_eq!(vendored.packages[0].path, dir.join("vendor/bitflags-0.7.0"));
+    assert!(vendored.config.source.contains_key("vendored-sources"));
+    assert!(dir.join("vendor/bitflags-0.7.0/Cargo.toml").exists());
+}
+
+#[test]
+fn write_config_preserves_other_settings() {
+    use cargo_vendor::{VendorConfig, VendorSource};
+
+    let (dir, _lock) = dir();
+
+    file(&dir, ".cargo/config", r#"
+        [build]
+        jobs = 2
+
+        [net]
+        offline = true
+
+        [source.crates-io]
+        replace-with = 'vendor+crates-io'
+
+        [source."vendor+crates-io"]
+        directory = 'old-vendor/registry-0123456789abcdef'
+
+        [source.mirror]
+        registry = 'https://example.com/index'
+    "#);
+
+    let mut source = std::collections::BTreeMap::new();
+    source.insert("crates-io".to_string(), VendorSource::Registry {
+        registry: None,
+        replace_with: "vendored-sources".to_string(),
+    });
+    source.insert("vendored-sources".to_string(), VendorSource::Directory {
+        directory: PathBuf::from("vendor"),
+    });
+    let config = VendorConfig { source };
+
+    let path = dir.join(".cargo/config");
+    cargo_vendor::write_config(&config, &path).unwrap();
+    let first = read(&path);
+    let table: toml::Value = first.parse().unwrap();
+    assert_eq!(table["build"]["jobs"].as_integer(), Some(2));
+    assert_eq!(table["net"]["offline"].as_bool(), Some(true));
+    assert!(table["source"].get("mirror").is_some());
+    assert!(table["source"].get("vendor+crates-io").is_none());
+    assert_eq!(table["source"]["crates-io"]["replace-with"].as_str(),
+               Some("vendored-sources"));
+    assert_eq!(table["source"]["vendored-sources"]["directory"].as_str(),
+               Some("vendor"));
+
+    cargo_vendor::write_config(&config, &path).unwrap();
+    assert_eq!(read(&path), first);
+}
+
+#[test]
+fn write_config_flag() {
+    let (dir, _lock) = dir();
+
+    file(&dir, "Cargo.toml", r#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+
+        [dependencies]
+        bitflags = "=0.7.0"
+    "#);
+    file(&dir, "src/lib.rs", "");
+    file(&dir, ".cargo/config", r#"
+        [build]
+        jobs = 2
+    "#);
+
+    let (output, _) = run(vendor(&dir).arg("--write-config").arg("--relative-path"));
+    assert!(output.is_empty());
+    let config = read(&dir.join(".cargo/config"));
+    assert!(config.contains("jobs = 2"));
+    assert!(config.contains("replace-with = \"vendored-sources\""));
+
+    run(vendor(&dir).arg("--write-config").arg("--relative-path"));
+    assert_eq!(read(&dir.join(".cargo/config")), config);
+
+    // The vendor directory isn't taken as the path of the configuration
+    let output = vendor(&dir).arg("--write-config").arg("third_party/vendor").output().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("use `--write-config=VALUE`"));
+    assert!(!dir.join("third_party").exists());
+
+    run(Command::new("cargo").arg("build").current_dir(&dir));
+}
+
+#[test]
+fn local_registry() {
+    let (dir, _lock) = dir();
+
+    file(&dir, "Cargo.toml", r#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+
+        [dependencies]
+        bitflags = "=0.8.0"
+        log = "=0.3.5"
+    "#);
+    file(&dir, "src/lib.rs", "");
+
+    let (output, _) = run(vendor(&dir).arg("--format").arg("local-registry"));
+    assert!(output.contains("local-registry = "));
+    assert!(dir.join("vendor/bitflags-0.8.0.crate").is_file());
+    assert!(dir.join("vendor/log-0.3.5.crate").is_file());
+    let index = read(&dir.join("vendor/index/bi/tf/bitflags"));
+    assert!(index.contains("\"vers\":\"0.8.0\""));
+    assert!(dir.join("vendor/index/3/l/log").is_file());
+
+    add_specific_vendor_config(&dir, r#"
+        [source.crates-io]
+        replace-with = 'vendor'
+
+        [source.vendor]
+        local-registry = 'vendor'
+    "#);
+    run(Command::new("cargo").arg("build").current_dir(&dir));
+}
+
+#[test]
+fn verify() {
+    let (dir, _lock) = dir();
+
+    // sha256 of the empty string
+    let empty = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+    let checksum = format!(r#"{{"package":null,"files":{{"Cargo.toml":"{0}","src/lib.rs":"{0}"}}}}"#,
+                           empty);
+    for krate in &["a", "b", "c"] {
+        file(&dir, &format!("vendor/{}/Cargo.toml", krate), "");
+        file(&dir, &format!("vendor/{}/src/lib.rs", krate), "");
+        file(&dir, &format!("vendor/{}/.cargo-checksum.json", krate), &checksum);
+    }
+
+    let (_, stderr) = run(vendor(&dir).arg("verify"));
+    assert!(stderr.contains("Verified 3 vendored crates"));
+
+    file(&dir, "vendor/a/src/lib.rs", "fn evil() {}");
+    fs::remove_file(dir.join("vendor/b/src/lib.rs")).unwrap();
+    file(&dir, "vendor/c/build.rs", "");
+
+    let output = vendor(&dir).arg("verify").output().unwrap();
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("a: modified file `src/lib.rs`"));
+    assert!(stdout.contains("b: missing file `src/lib.rs`"));
+    assert!(stdout.contains("c: extra file `build.rs`"));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("3 of 3 vendored crates failed verification"));
+}
+
+#[test]
+fn platform_filter() {
+    let (dir, _lock) = dir();
+
+    file(&dir, "Cargo.toml", r#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+
+        [dependencies]
+        atty = "=0.2.11"
+    "#);
+    file(&dir, "src/lib.rs", "");
+
+    let (_, stderr) = run(vendor(&dir)
+        .arg("--platform").arg("x86_64-unknown-linux-gnu"));
+    assert!(dir.join("vendor/atty/src").is_dir());
+    assert!(dir.join("vendor/libc/src").is_dir());
+    assert!(dir.join("vendor/winapi/.cargo-vendor-stub").is_file());
+    assert_eq!(read(&dir.join("vendor/winapi/src/lib.rs")), "");
+    assert!(!dir.join("vendor/winapi/src/um").exists());
+    assert!(dir.join("vendor/winapi-x86_64-pc-windows-gnu/.cargo-vendor-stub").is_file());
+    assert!(stderr.contains("Skipping winapi v0.3"));
+    assert!(stderr.contains("only required by `atty` for `cfg(windows)`"));
+    assert!(stderr.contains("only required by other skipped packages"));
+
+    assert_vendor_works(&dir);
+}
+
+#[test]
+fn prune_optional_deps() {
+    let (dir, _lock) = dir();
+
+    file(&dir, "Cargo.toml", r#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+
+        [dependencies]
+        log = "=0.4.6"
+
+        [features]
+        ser = ["log/serde"]
+    "#);
+    file(&dir, "src/lib.rs", "");
+
+    let (_, stderr) = run(vendor(&dir).arg("--prune-optional-deps"));
+    assert!(dir.join("vendor/log/src").is_dir());
+    assert!(dir.join("vendor/serde/.cargo-vendor-stub").is_file());
+    assert!(stderr.contains("optional dependency of `log` which isn't activated"));
+    assert_vendor_works(&dir);
+
+    run(vendor(&dir).arg("--features").arg("ser"));
+    assert!(dir.join("vendor/serde/src/de").is_dir());
+    assert!(!dir.join("vendor/serde/.cargo-vendor-stub").exists());
+}
+
+#[test]
+fn no_dev_deps() {
+    let (dir, _lock) = dir();
+
+    file(&dir, "Cargo.toml", r#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+
+        [dependencies]
+        memchr = "=2.2.0"
+
+        [dev-dependencies]
+        bitflags = "=1.0.4"
+    "#);
+    file(&dir, "src/lib.rs", "");
+
+    let (_, stderr) = run(vendor(&dir).arg("--no-dev-deps"));
+    assert!(dir.join("vendor/bitflags/.cargo-vendor-stub").is_file());
+    assert!(stderr.contains("only required by `foo` as a dev-dependency"));
+    let manifest = read(&dir.join("vendor/memchr/Cargo.toml"));
+    assert!(!manifest.contains("quickcheck"));
+    run(vendor(&dir).arg("verify"));
+    assert_vendor_works(&dir);
+}
+
+#[test]
+fn strip_files() {
+    let (dir, _lock) = dir();
+
+    file(&dir, "Cargo.toml", r#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+
+        [dependencies]
+        log = "=0.4.6"
+    "#);
+    file(&dir, "src/lib.rs", "");
+
+    run(vendor(&dir).arg("--strip").arg("tests").arg("--strip").arg("*.md"));
+    assert!(dir.join("vendor/log/src/lib.rs").is_file());
+    assert!(!dir.join("vendor/log/tests").exists());
+    assert!(!dir.join("vendor/log/README.md").exists());
+    let manifest = read(&dir.join("vendor/log/Cargo.toml"));
+    assert!(!manifest.contains("filters"));
+    let checksums = read(&dir.join("vendor/log/.cargo-checksum.json"));
+    assert!(!checksums.contains("tests/filters.rs"));
+    run(vendor(&dir).arg("verify"));
+    assert_vendor_works(&dir);
+}
+
+#[test]
+fn apply_patches() {
+    let (dir, _lock) = dir();
+
+    file(&dir, "Cargo.toml", r#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+
+        [dependencies]
+        log = "=0.4.6"
+    "#);
+    file(&dir, "src/lib.rs", "");
+    file(&dir, "patches/log-0.4.6/0001-add-file.patch", r#"--- /dev/null
++++ b/PATCHED.txt
+@@ -0,0 +1 @@
++patched
+"#);
+
+    run(vendor(&dir).arg("--patches").arg("patches"));
+    assert_eq!(read(&dir.join("vendor/log/PATCHED.txt")), "patched\n");
+    let checksums = read(&dir.join("vendor/log/.cargo-checksum.json"));
+    assert!(checksums.contains("PATCHED.txt"));
+    run(vendor(&dir).arg("verify"));
+    assert_vendor_works(&dir);
+
+    fs::rename(dir.join("patches/log-0.4.6"), dir.join("patches/log-0.4.5")).unwrap();
+    let output = vendor(&dir).arg("--patches").arg("patches").output().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("are for log v0.4.5, but v0.4.6 is vendored"));
+}
+
+#[test]
+fn sbom() {
+    let (dir, _lock) = dir();
+
+    file(&dir, "Cargo.toml", r#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+
+        [dependencies]
+        log = "=0.4.6"
+    "#);
+    file(&dir, "src/lib.rs", "");
+
+    run(vendor(&dir)
+        .arg("--sbom-spdx").arg("sbom.spdx.json")
+        .arg("--sbom-cyclonedx").arg("sbom.cdx.json")
+        .env("SOURCE_DATE_EPOCH", "1560000000"));
+
+    let spdx = read(&dir.join("sbom.spdx.json"));
+    assert!(spdx.contains("\"SPDX-2.2\""));
+    assert!(spdx.contains("\"2019-06-08T13:20:00Z\""));
+    assert!(spdx.contains("https://crates.io/api/v1/crates/log/0.4.6/download"));
+    assert!(spdx.contains("\"MIT OR Apache-2.0\""));
+    assert!(spdx.contains("\"DEPENDS_ON\""));
+
+    let cyclonedx = read(&dir.join("sbom.cdx.json"));
+    assert!(cyclonedx.contains("\"CycloneDX\""));
+    assert!(cyclonedx.contains("\"pkg:cargo/log@0.4.6\""));
+    assert!(cyclonedx.contains("\"pkg:cargo/cfg-if@0.1."));
+    assert!(cyclonedx.contains("\"dependsOn\""));
+}
+
+#[test]
+fn third_party_notices() {
+    let (dir, _lock) = dir();
+
+    file(&dir, "Cargo.toml", r#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+
+        [dependencies]
+        log = "=0.4.6"
+    "#);
+    file(&dir, "src/lib.rs", "");
+
+    run(vendor(&dir)
+        .arg("--notices").arg("NOTICES.md")
+        .arg("--notices-format").arg("markdown"));
+    let notices = read(&dir.join("NOTICES.md"));
+    assert!(notices.contains("## MIT OR Apache-2.0"));
+    assert!(notices.contains("### log 0.4.6"));
+    assert!(notices.contains("#### LICENSE-MIT"));
+    assert!(notices.contains("#### LICENSE-APACHE"));
+    assert!(!notices.contains("Crates without a license text"));
+
+    run(vendor(&dir).arg("--notices").arg("THIRD_PARTY_NOTICES"));
+    let notices = read(&dir.join("THIRD_PARTY_NOTICES"));
+    assert!(notices.starts_with("THIRD PARTY NOTICES"));
+    assert!(notices.contains("Permission is hereby granted"));
+}
+
+#[test]
+fn vendor_diff() {
+    let (dir, _lock) = dir();
+
+    file(&dir, "Cargo.toml", r#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+
+        [dependencies]
+        log = "=0.4.5"
+    "#);
+    file(&dir, "src/lib.rs", "");
+    run(&mut vendor(&dir));
+
+    file(&dir, "Cargo.toml", r#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+
+        [dependencies]
+        log = "=0.4.6"
+        bitflags = "=1.0.4"
+    "#);
+    file(&dir, "vendor/old/Cargo.toml", r#"
+        [project]
+        name = "old"
+        version = "0.1.0"
+    "#);
+    file(&dir, "vendor/broken/Cargo.toml", "[package");
+    let (_, stderr) = run(vendor(&dir).arg("--diff").arg("--diff-json").arg("diff.json"));
+    assert!(stderr.contains("upgraded"));
+
+    let diff = read(&dir.join("diff.json"));
+    let diff: serde_json::Value = serde_json::from_str(&diff).unwrap();
+    let crates = diff["crates"].as_array().unwrap();
+    let bitflags = crates.iter().find(|c| c["name"] == "bitflags").unwrap();
+    assert_eq!(bitflags["kind"], "added");
+    assert_eq!(bitflags["new_version"], "1.0.4");
+    let log = crates.iter().find(|c| c["name"] == "log").unwrap();
+    assert_eq!(log["kind"], "upgraded");
+    assert_eq!(log["old_version"], "0.4.5");
+    assert_eq!(log["new_version"], "0.4.6");
+    assert!(log["new_source"].as_str().unwrap().starts_with("registry+"));
+    assert!(!log["files"]["modified"].as_array().unwrap().is_empty());
+}
+
+#[test]
+fn message_format_json() {
+    let (dir, _lock) = dir();
+
+    file(&dir, "Cargo.toml", r#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+
+        [dependencies]
+        bitflags = "=1.0.4"
+    "#);
+    file(&dir, "src/lib.rs", "");
+    let (stdout, _) = run(vendor(&dir).arg("--message-format").arg("json"));
+
+    let messages = stdout
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(messages[0]["reason"], "resolve");
+    let copy = messages.iter().find(|m| m["reason"] == "copy").unwrap();
+    assert!(copy["package_id"].as_str().unwrap().starts_with("bitflags v1.0.4"));
+    assert!(copy["dst_path"].as_str().unwrap().ends_with("bitflags"));
+    let config = messages.last().unwrap();
+    assert_eq!(config["reason"], "config");
+    let directory = &config["config"]["source"]["vendored-sources"]["directory"];
+    assert!(directory.as_str().unwrap().ends_with("vendor"));
+}
+
+#[test]
+fn unchanged_crates_kept() {
+    let (dir, _lock) = dir();
+
+    file(&dir, "Cargo.toml", r#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+
+        [dependencies]
+        bitflags = "=1.0.4"
+    "#);
+    file(&dir, "src/lib.rs", "");
+    run(&mut vendor(&dir));
+
+    // A crate which is copied again loses files it didn't come with
+    file(&dir, "vendor/bitflags/marker", "");
+    let (stdout, _) = run(vendor(&dir).arg("--message-format").arg("json"));
+    assert!(stdout.contains("\"reason\":\"fresh\""));
+    assert!(!stdout.contains("\"reason\":\"copy\""));
+    assert!(dir.join("vendor/bitflags/marker").exists());
+
+    run(vendor(&dir).arg("--strip").arg("tests"));
+    assert!(!dir.join("vendor/bitflags/marker").exists());
+}
+
+#[test]
+fn link_mode_hardlink() {
+    let (dir, _lock) = dir();
+
+    file(&dir, "Cargo.toml", r#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+
+        [dependencies]
+        memchr = "=2.2.0"
+    "#);
+    file(&dir, "src/lib.rs", "");
+    run(vendor(&dir).arg("--link-mode").arg("hardlink").arg("--no-dev-deps"));
+    run(vendor(&dir).arg("verify"));
+
+    // Files are only linked if the vendor directory is on the same file
+    // system as Cargo's cache, otherwise they're copied.
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        let lib = fs::metadata(dir.join("vendor/memchr/src/lib.rs")).unwrap();
+        if lib.nlink() > 1 {
+            assert!(lib.permissions().readonly());
+        }
+        let manifest = fs::metadata(dir.join("vendor/memchr/Cargo.toml")).unwrap();
+        assert_eq!(manifest.nlink(), 1);
+    }
+}
+
+#[test]
+fn dedup_files() {
+    let (dir, _lock) = dir();
+
+    file(&dir, "Cargo.toml", r#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+
+        [dependencies]
+        bitflags = "=1.0.4"
+        old-bitflags = { package = "bitflags", version = "=0.9.1" }
+    "#);
+    file(&dir, "src/lib.rs", "");
+    let (_, stderr) = run(vendor(&dir).arg("--dedup-symlinks"));
+    assert!(stderr.contains("Deduplicated"));
+
+    // The older version comes first, so the newer one links to its files
+    #[cfg(unix)]
+    assert_eq!(
+        fs::read_link(dir.join("vendor/bitflags/LICENSE-APACHE")).unwrap(),
+        Path::new("../bitflags-0.9.1/LICENSE-APACHE")
+    );
+    run(vendor(&dir).arg("verify"));
+
+    // Crates with links are left alone if what they link to is
+    let (stdout, _) = run(vendor(&dir)
+        .arg("--dedup-symlinks")
+        .arg("--message-format")
+        .arg("json"));
+    assert!(stdout.contains("\"reason\":\"fresh\""));
+    assert!(!stdout.contains("\"reason\":\"copy\""));
+}
+
+#[test]
+fn audit() {
+    let (dir, _lock) = dir();
+
+    for &(name, version) in &[("smallvec", "0.6.9"), ("time", "0.1.42"), ("libc", "0.2.58")] {
+        file(&dir, &format!("vendor/{}/Cargo.toml", name), &format!(r#"
+            [package]
+            name = "{}"
+            version = "{}"
+        "#, name, version));
+    }
+    file(&dir, "advisory-db/crates/smallvec/RUSTSEC-2019-0009.md", r#"```toml
+[advisory]
+id = "RUSTSEC-2019-0009"
+package = "smallvec"
+date = "2019-06-06"
+url = "https://github.com/servo/rust-smallvec/issues/148"
+
+[versions]
+patched = [">= 0.6.10"]
+unaffected = ["< 0.6.5"]
+```
+
+# Double-free and use-after-free in SmallVec::grow()
+"#);
+    file(&dir, "advisory-db/crates/time/RUSTSEC-2020-0071.toml", r#"
+        [advisory]
+        id = "RUSTSEC-2020-0071"
+        package = "time"
+        title = "Potential segfault in the time crate"
+
+        [versions]
+        patched = [">= 0.2.23"]
+        unaffected = ["= 0.2.0", "= 0.2.1", "= 0.2.2"]
+    "#);
+    file(&dir, "advisory-db/crates/libc/RUSTSEC-2019-0999.toml", r#"
+        [advisory]
+        id = "RUSTSEC-2019-0999"
+        package = "libc"
+        title = "Fixed long ago"
+
+        [versions]
+        patched = [">= 0.2.50"]
+    "#);
+
+    let output = vendor(&dir).arg("audit").arg("--advisory-db").arg("advisory-db")
+        .output().unwrap();
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("smallvec 0.6.9 is affected by RUSTSEC-2019-0009: \
+                             Double-free and use-after-free in SmallVec::grow()"));
+    assert!(stdout.contains("patched: >= 0.6.10"));
+    assert!(stdout.contains("time 0.1.42 is affected by RUSTSEC-2020-0071"));
+    assert!(!stdout.contains("libc"));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("2 vulnerabilities found in 2 of 3 vendored crates"));
+
+    file(&dir, "audit-ignore.toml", r#"
+        [[ignore]]
+        id = "RUSTSEC-2020-0071"
+    "#);
+    let output = vendor(&dir).arg("audit").arg("--advisory-db").arg("advisory-db")
+        .arg("--audit-ignore").arg("audit-ignore.toml")
+        .output().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("without a reason"));
+
+    // Library callers can't skip the reason either
+    let ignores = [cargo_vendor::AuditIgnore {
+        id: "RUSTSEC-2020-0071".to_string(),
+        reason: " ".to_string(),
+    }];
+    let result = cargo_vendor::audit(&dir.join("vendor"), &dir.join("advisory-db"), &ignores);
+    assert!(result.is_err());
+
+    file(&dir, "audit-ignore.toml", r#"
+        [[ignore]]
+        id = "RUSTSEC-2020-0071"
+        reason = "we never call localtime_r"
+
+        [[ignore]]
+        id = "RUSTSEC-2019-0009"
+        reason = "only used with Copy types"
+    "#);
+    let (_, stderr) = run(vendor(&dir).arg("audit").arg("--advisory-db").arg("advisory-db")
+        .arg("--audit-ignore").arg("audit-ignore.toml"));
+    assert!(stderr.contains("we never call localtime_r"));
+    assert!(stderr.contains("Audited 3 vendored crates"));
+}
+
+#[test]
+fn vendor_policy() {
+    let (dir, _lock) = dir();
+
+    file(&dir, "Cargo.toml", r#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+
+        [dependencies]
+        log = "=0.4.6"
+        bitflags = "=1.0.4"
+    "#);
+    file(&dir, "src/lib.rs", "");
+    file(&dir, "vendor-policy.toml", r#"
+        [crates]
+        deny = ["log < 0.4.7"]
+
+        [licenses]
+        allow = ["BSD-3-Clause"]
+    "#);
+
+    let output = vendor(&dir).output().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("is violated by 2 packages"));
+    assert!(stderr.contains("log v0.4.6: denied by `log < 0.4.7`"));
+    assert!(stderr.contains("bitflags v1.0.4: license `MIT OR Apache-2.0` isn't allowed"));
+    assert!(!dir.join("vendor").exists());
+
+    file(&dir, "vendor-policy.toml", r#"
+        [licenses]
+        allow = ["BSD-3-Clause"]
+
+        [sources]
+        allow = ["crates-io"]
+
+        [[exceptions]]
+        name = "log"
+        licenses = ["MIT"]
+    "#);
+    let output = vendor(&dir).output().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("the exception for `log`"));
+    assert!(stderr.contains("has no reason"));
+
+    file(&dir, "vendor-policy.toml", r#"
+        [licenses]
+        allow = ["BSD-3-Clause"]
+
+        [sources]
+        allow = ["crates-io"]
+
+        [[exceptions]]
+        name = "log"
+        licenses = ["MIT"]
+        reason = "reviewed"
+    "#);
+    let output = vendor(&dir).output().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("is violated by 1 package:"));
+    assert!(!stderr.contains("log v0.4.6"));
+
+    // Sources are matched on whole path segments
+    file(&dir, "vendor-policy.toml", r#"
+        [sources]
+        allow = ["https://github.com/rust-lang/crates", "https://github.co"]
+    "#);
+    let output = vendor(&dir).output().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("is violated by 2 packages"));
+    assert!(stderr.contains("source `https://github.com/rust-lang/crates.io-index` isn't allowed"));
+
+    file(&dir, "vendor-policy.toml", r#"
+        [sources]
+        allow = ["https://github.com/rust-lang/"]
+    "#);
+    run(&mut vendor(&dir));
+
+    file(&dir, "vendor-policy.toml", r#"
+        [licenses]
+        allow = ["Apache-2.0"]
+    "#);
+    run(&mut vendor(&dir));
+    assert!(dir.join("vendor/log/Cargo.toml").exists());
+}
+
+#[test]
+fn alternative_registry() {
+    let (dir, _lock) = dir();
+
+    file(&dir, "Cargo.toml", r#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+
+        [dependencies]
+        bitflags = { version = "=1.0.4", registry = "mirror" }
+    "#);
+    file(&dir, "src/lib.rs", "");
+    file(&dir, ".cargo/config", r#"
+        [registries.mirror]
+        index = "https://github.com/rust-lang/crates.io-index.git"
+    "#);
+
+    run(vendor(&dir).arg("--no-merge-sources").arg("--write-config"));
+    assert!(dir.join("vendor/registry-mirror/bitflags/Cargo.toml").exists());
+    let config = read(&dir.join(".cargo/config"));
+    assert!(config.contains("[registries.mirror]"));
+    assert!(config.contains("[source.mirror]"));
+    assert!(config.contains("registry = \"https://github.com/rust-lang/crates.io-index.git\""));
+    assert!(config.contains("replace-with = \"vendor+mirror\""));
+    run(Command::new("cargo").arg("build").current_dir(&dir));
+}
+
+#[test]
+fn include_path_deps() {
+    let (dir, _lock) = dir();
+
+    // `cargo-features` sorts after the `badges` table when rewritten
+    file(&dir, "common/liba/Cargo.toml", r#"
+        cargo-features = []
+
+        [package]
+        name = "liba"
+        version = "0.1.0"
+
+        [badges]
+        maintenance = { status = "passively-maintained" }
+
+        [dependencies]
+        libb = { path = "../libb" }
+    "#);
+    file(&dir, "common/liba/src/lib.rs", "pub fn a() -> u32 { libb::b() }");
+    file(&dir, "common/libb/Cargo.toml", r#"
+        [package]
+        name = "libb"
+        version = "0.1.0"
+    "#);
+    file(&dir, "common/libb/src/lib.rs", "pub fn b() -> u32 { 2 }");
+    let svc = dir.join("svc");
+    file(&svc, "Cargo.toml", r#"
+        [package]
+        name = "svc"
+        version = "0.1.0"
+
+        [dependencies]
+        liba = { path = "../common/liba" }
+    "#);
+    file(&svc, "src/lib.rs", "pub fn svc() -> u32 { liba::a() }");
+
+    // A pattern has to be given with `=`, as a bare flag followed by another
+    // argument is ambiguous
+    let output = vendor(&svc).arg("--include-path-deps").arg("libb").output().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("use `--include-path-deps=VALUE`"));
+    assert!(!svc.join("vendor").exists());
+
+    run(vendor(&svc).arg("--include-path-deps=libb"));
+    assert!(!svc.join("vendor/liba").exists());
+    assert!(!svc.join("libb").exists());
+
+    let (_, stderr) = run(vendor(&svc).arg("--include-path-deps").arg("--relative-path"));
+    assert!(stderr.contains("-> vendor/liba"));
+    assert!(stderr.contains("-> vendor/libb"));
+    assert!(svc.join("vendor/libb/src/lib.rs").exists());
+    let manifest = read(&svc.join("vendor/liba/Cargo.toml"));
+    assert!(manifest.contains("path = \"../libb\""));
+
+    // The service builds without the original path dependencies
+    fs::remove_dir_all(dir.join("common")).unwrap();
+    let manifest = read(&svc.join("Cargo.toml")).replace("../common/liba", "vendor/liba");
+    file(&svc, "Cargo.toml", &manifest);
+    run(Command::new("cargo").arg("build").current_dir(&svc));
+}
+
+fn git(dir: &Path, args: &[&str]) {
+    run(Command::new("git")
+        .args(&["-c", "user.name=vendor", "-c", "user.email=vendor@example.com"])
+        .args(&["-c", "protocol.file.allow=always"])
+        .args(args)
+        .current_dir(dir));
+}
+
+#[test]
+fn git_submodules_and_lfs() {
+    let (dir, _lock) = dir();
+
+    let sub = dir.join("csrc");
+    file(&sub, "Cargo.toml", r#"
+        [package]
+        name = "csrc-meta"
+        version = "0.0.0"
+    "#);
+    file(&sub, "include/c.h", "int c(void);\n");
+    git(&sub, &["init", "-q"]);
+    git(&sub, &["add", "-A"]);
+    git(&sub, &["commit", "-q", "-m", "c"]);
+
+    let dep = dir.join("withsub");
+    file(&dep, "Cargo.toml", r#"
+        [package]
+        name = "withsub"
+        version = "0.1.0"
+    "#);
+    file(&dep, "src/lib.rs", "");
+    file(&dep, "data.bin", "version https://git-lfs.github.com/spec/v1\n\
+        oid sha256:4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393\n\
+        size 12345\n");
+    git(&dep, &["init", "-q"]);
+    git(&dep, &["submodule", "-q", "add", &format!("file://{}", sub.display()), "csrc"]);
+    git(&dep, &["add", "-A"]);
+    git(&dep, &["commit", "-q", "-m", "withsub"]);
+
+    let project = dir.join("foo");
+    file(&project, "Cargo.toml", &format!(r#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+
+        [dependencies]
+        withsub = {{ git = "file://{}" }}
+    "#, dep.display()));
+    file(&project, "src/lib.rs", "");
+
+    let output = vendor(&project).output().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("contains Git LFS pointer files"));
+    assert!(stderr.contains("  data.bin"));
+
+    run(vendor(&project).arg("--strip").arg("data.bin"));
+    assert!(project.join("vendor/withsub/csrc/include/c.h").exists());
+    assert!(project.join("vendor/withsub/csrc/Cargo.toml").exists());
+    assert!(!project.join("vendor/withsub/data.bin").exists());
+
+    // The package's own exclude rules apply to the submodule's files
+    let manifest = read(&dep.join("Cargo.toml"));
+    file(&dep, "Cargo.toml", &format!("{}exclude = [\"csrc/include\", \"data.bin\"]\n", manifest));
+    git(&dep, &["commit", "-q", "-a", "-m", "exclude"]);
+    run(Command::new("cargo").arg("update").current_dir(&project));
+    run(&mut vendor(&project));
+    assert!(project.join("vendor/withsub/csrc/Cargo.toml").exists());
+    assert!(!project.join("vendor/withsub/csrc/include/c.h").exists());
+}
+
+#[test]
+fn vendor_info() {
+    let (dir, _lock) = dir();
+
+    file(&dir, "Cargo.toml", r#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+
+        [dependencies]
+        log = "=0.4.6"
+    "#);
+    file(&dir, "src/lib.rs", "");
+
+    run(&mut vendor(&dir));
+    let info = read(&dir.join("vendor/log/.cargo-vendor-info.json"));
+    assert!(info.contains(r#""source": "registry+https://github.com/rust-lang/crates.io-index""#));
+    assert!(info.contains(r#""registry": "https://github.com/rust-lang/crates.io-index""#));
+    assert!(info.contains(r#""checksum": ""#));
+    assert!(info.contains(r#""Cargo.lock""#));
+    assert!(info.contains(r#""vendored_by": "cargo-vendor "#));
+    run(vendor(&dir).arg("verify"));
+}
+
+#[test]
+fn branch_deps() {
+    let (dir, _lock) = dir();
+
+    for name in ["onbranch", "pinned"].iter() {
+        let dep = dir.join(name);
+        file(&dep, "Cargo.toml", &format!(r#"
+            [package]
+            name = "{}"
+            version = "0.1.0"
+        "#, name));
+        file(&dep, "src/lib.rs", "");
+        git(&dep, &["init", "-q"]);
+        git(&dep, &["symbolic-ref", "HEAD", "refs/heads/master"]);
+        git(&dep, &["add", "-A"]);
+        git(&dep, &["commit", "-q", "-m", name]);
+    }
+    let (onbranch, _) = run(Command::new("git")
+        .args(&["rev-parse", "HEAD"])
+        .current_dir(dir.join("onbranch")));
+    let (pinned, _) = run(Command::new("git")
+        .args(&["rev-parse", "HEAD"])
+        .current_dir(dir.join("pinned")));
+
+    let project = dir.join("foo");
+    file(&project, "Cargo.toml", &format!(r#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+
+        [dependencies]
+        onbranch = {{ git = "file://{}", branch = "master" }}
+        pinned = {{ git = "file://{}", rev = "{}" }}
+    "#, dir.join("onbranch").display(), dir.join("pinned").display(), pinned.trim()));
+    file(&project, "src/lib.rs", "");
+
+    let output = vendor(&project).arg("--branch-deps").arg("deny").output().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("1 git package is only pinned to a branch"));
+    assert!(stderr.contains(&format!("follows the branch `master`, locked at {}", onbranch.trim())));
+    assert!(!stderr.contains("pinned v0.1.0"));
+    assert!(!project.join("vendor").exists());
+
+    let (_, stderr) = run(vendor(&project).arg("--branch-deps").arg("warn"));
+    assert!(stderr.contains("warning: onbranch v0.1.0"));
+    let info = read(&project.join("vendor/onbranch/.cargo-vendor-info.json"));
+    assert!(info.contains(r#""branch": "master""#));
+    assert!(info.contains(&format!(r#""commit": "{}""#, onbranch.trim())));
+
+    let (_, stderr) = run(&mut vendor(&project));
+    assert!(!stderr.contains("warning: onbranch"));
+}
+
+#[test]
+fn dir_format() {
+    let (dir, _lock) = dir();
+
+    let mut revs = Vec::new();
+    for name in ["first", "second"].iter() {
+        let dep = dir.join(name);
+        file(&dep, "Cargo.toml", &format!(r#"
+            [package]
+            name = "{}"
+            version = "0.1.0"
+        "#, name));
+        file(&dep, "src/lib.rs", "");
+        git(&dep, &["init", "-q"]);
+        git(&dep, &["add", "-A"]);
+        git(&dep, &["commit", "-q", "-m", name]);
+        let (rev, _) = run(Command::new("git").args(&["rev-parse", "HEAD"]).current_dir(&dep));
+        revs.push(rev.trim()[..8].to_string());
+    }
+
+    let project = dir.join("foo");
+    file(&project, "Cargo.toml", &format!(r#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+
+        [dependencies]
+        first = {{ git = "file://{}" }}
+        second = {{ git = "file://{}" }}
+    "#, dir.join("first").display(), dir.join("second").display()));
+    file(&project, "src/lib.rs", "");
+
+    run(vendor(&project).arg("--dir-format").arg("{name}-{version}-{rev}"));
+    assert!(project.join(format!("vendor/first-0.1.0-{}/Cargo.toml", revs[0])).exists());
+    assert!(project.join(format!("vendor/second-0.1.0-{}/Cargo.toml", revs[1])).exists());
+
+    let output = vendor(&project).arg("--dir-format").arg("{source}").output().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("would both be vendored into `vendor/git`"), "{}", stderr);
+    assert!(project.join(format!("vendor/first-0.1.0-{}", revs[0])).exists());
+
+    let output = vendor(&project)
+        .args(&["--no-merge-sources", "--dir-format", "{source}/{name}"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("the sources `"), "{}", stderr);
+
+    run(vendor(&project).args(&["--no-merge-sources", "--dir-format", "{source}-{hash}/{name}-{version}"]));
+    let sources = read(&project.join("vendor/.sources"));
+    let sources: Vec<String> = serde_json::from_str(&sources).unwrap();
+    assert_eq!(sources.len(), 2);
+    let crates = sources
+        .iter()
+        .flat_map(|source| fs::read_dir(project.join("vendor").join(source)).unwrap())
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+    assert!(sources.iter().all(|source| source.starts_with("git-")));
+    assert!(crates.contains(&"first-0.1.0".to_string()));
+    assert!(crates.contains(&"second-0.1.0".to_string()));
+
+    let output = vendor(&project).arg("--dir-format").arg("{source}/{name}").output().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("which needs sources not to be merged"), "{}", stderr);
+
+    let output = vendor(&project).arg("--dir-format").arg("{name}/{version}").output().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("`{name}` can only be used in the last component"), "{}", stderr);
+}